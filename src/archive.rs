@@ -0,0 +1,334 @@
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use rocket::serde::json::serde_json as json;
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+
+use crate::{
+    athlete::Athlete,
+    clock::ClockSample,
+    run::{RawData, SummaryOptions, Tickstamp},
+    timeline::Discontinuity,
+};
+
+// version 1 is the uncompressed {startTime, ticks} json written before archives were versioned
+pub const ARCHIVE_VERSION: u32 = 2;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+// where anonymized runs start, in epoch millis
+const ANONYMOUS_START: u64 = 1656202584971;
+
+#[derive(Debug)]
+pub struct ArchiveError {
+    pub msg: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct TreadmillProfile {
+    #[serde(rename = "ticksPerMile")]
+    pub ticks_per_mile: f32,
+    #[serde(rename = "debounceTime")]
+    pub debounce_time: u32, // in millis
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct AthleteParameters {
+    #[serde(rename = "athleteId")]
+    pub athlete_id: String,
+    #[serde(rename = "weight")]
+    pub weight: f32, // in pounds
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct PauseWindow {
+    #[serde(rename = "start")]
+    pub start: Tickstamp,
+    #[serde(rename = "end")]
+    pub end: Tickstamp,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct IngestionMetadata {
+    #[serde(rename = "finalizedAt")]
+    pub finalized_at: u64, // epoch millis
+    #[serde(rename = "serverVersion")]
+    pub server_version: String,
+    #[serde(rename = "droppedTicks")]
+    pub dropped_ticks: usize, // cached entries that were not valid tickstamps
+    // set when the ticks were synthesized from an imported file, e.g. "tcx"
+    #[serde(rename = "importedFrom", default)]
+    pub imported_from: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Archive {
+    #[serde(rename = "version")]
+    pub version: u32,
+    #[serde(rename = "runId")]
+    pub run_id: String,
+    #[serde(rename = "startTime")]
+    pub start_time: String,
+    #[serde(rename = "treadmill")]
+    pub treadmill: Option<TreadmillProfile>,
+    #[serde(rename = "athlete")]
+    pub athlete: Option<AthleteParameters>,
+    #[serde(rename = "pauses", default)]
+    pub pauses: Vec<PauseWindow>,
+    #[serde(rename = "ingestion")]
+    pub ingestion: Option<IngestionMetadata>,
+    #[serde(rename = "clockSamples", default)]
+    pub clock_samples: Vec<ClockSample>,
+    #[serde(rename = "discontinuities", default)]
+    pub discontinuities: Vec<Discontinuity>,
+    // first tick as is, then the difference to the previous tick
+    #[serde(rename = "tickDeltas")]
+    tick_deltas: Vec<i64>,
+}
+
+#[derive(Deserialize)]
+struct LegacyArchive {
+    #[serde(rename = "startTime")]
+    start_time: String,
+    #[serde(rename = "ticks")]
+    ticks: Vec<Tickstamp>,
+}
+
+fn delta_encode(ticks: &[Tickstamp]) -> Vec<i64> {
+    let mut prev = 0;
+    ticks
+        .iter()
+        .map(|t| {
+            let delta = *t as i64 - prev;
+            prev = *t as i64;
+            delta
+        })
+        .collect()
+}
+
+fn delta_decode(deltas: &[i64]) -> Result<Vec<Tickstamp>, ArchiveError> {
+    let mut prev: i64 = 0;
+    deltas
+        .iter()
+        .map(|d| {
+            prev += d;
+            Tickstamp::try_from(prev).map_err(|_| ArchiveError {
+                msg: format!("tick {} is out of range", prev),
+            })
+        })
+        .collect()
+}
+
+impl Archive {
+    pub fn new(
+        run_id: &str,
+        raw_data: &RawData,
+        athlete: &Athlete,
+        options: &SummaryOptions,
+        ingestion: IngestionMetadata,
+    ) -> Archive {
+        Archive {
+            version: ARCHIVE_VERSION,
+            run_id: run_id.to_string(),
+            start_time: raw_data.start_time.clone(),
+            treadmill: Some(TreadmillProfile {
+                ticks_per_mile: options.ticks_per_mile,
+                debounce_time: options.debounce_time,
+            }),
+            athlete: Some(AthleteParameters {
+                athlete_id: athlete.id.clone(),
+                weight: athlete.weight,
+            }),
+            pauses: raw_data
+                .pauses(options)
+                .into_iter()
+                .map(|(start, end)| PauseWindow { start, end })
+                .collect(),
+            ingestion: Some(ingestion),
+            clock_samples: raw_data.clock_samples.clone(),
+            discontinuities: raw_data.discontinuities.clone(),
+            tick_deltas: delta_encode(&raw_data.tickstamps),
+        }
+    }
+
+    pub fn raw_data(&self) -> Result<RawData, ArchiveError> {
+        Ok(RawData {
+            start_time: self.start_time.clone(),
+            tickstamps: delta_decode(&self.tick_deltas)?,
+            clock_samples: self.clock_samples.clone(),
+            discontinuities: self.discontinuities.clone(),
+        })
+    }
+
+    // for checking a real run in as a golden: the athlete and the run's id and date
+    // are replaced, the ticks and what they are summarized with are kept as they are
+    pub fn anonymize(&mut self, name: &str) {
+        let shift = ANONYMOUS_START as i64 - self.start_time.parse::<i64>().unwrap_or_default();
+        let shifted = |time: u64| (time as i64 + shift).max(0) as u64;
+        self.run_id = name.to_string();
+        self.start_time = ANONYMOUS_START.to_string();
+        if let Some(athlete) = &mut self.athlete {
+            athlete.athlete_id = "anonymous".to_string();
+        }
+        if let Some(ingestion) = &mut self.ingestion {
+            ingestion.finalized_at = shifted(ingestion.finalized_at);
+        }
+        for sample in &mut self.clock_samples {
+            sample.wall_time = shifted(sample.wall_time);
+        }
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, ArchiveError> {
+        let data = json::to_vec(self).map_err(|e| ArchiveError {
+            msg: format!("error encoding archive: {}", e),
+        })?;
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder
+            .write_all(&data)
+            .and_then(|_| encoder.finish())
+            .map_err(|e| ArchiveError {
+                msg: format!("error compressing archive: {}", e),
+            })
+    }
+
+    // reads both current archives and the legacy uncompressed json
+    pub fn decode(run_id: &str, bytes: &[u8]) -> Result<Archive, ArchiveError> {
+        if !bytes.starts_with(&GZIP_MAGIC) {
+            return Archive::decode_legacy(run_id, bytes);
+        }
+        let mut data = vec![];
+        GzDecoder::new(bytes)
+            .read_to_end(&mut data)
+            .map_err(|e| ArchiveError {
+                msg: format!("error decompressing archive: {}", e),
+            })?;
+        let archive: Archive = json::from_slice(&data).map_err(|e| ArchiveError {
+            msg: format!("error decoding archive: {}", e),
+        })?;
+        if archive.version > ARCHIVE_VERSION {
+            return Err(ArchiveError {
+                msg: format!("archive version {} is not supported", archive.version),
+            });
+        }
+        Ok(archive)
+    }
+
+    fn decode_legacy(run_id: &str, bytes: &[u8]) -> Result<Archive, ArchiveError> {
+        let legacy: LegacyArchive = json::from_slice(bytes).map_err(|e| ArchiveError {
+            msg: format!("error decoding legacy archive: {}", e),
+        })?;
+        Ok(Archive {
+            version: 1,
+            run_id: run_id.to_string(),
+            start_time: legacy.start_time,
+            treadmill: None,
+            athlete: None,
+            pauses: vec![],
+            ingestion: None,
+            clock_samples: vec![],
+            discontinuities: vec![],
+            tick_deltas: delta_encode(&legacy.ticks),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::athlete;
+
+    fn test_archive(tickstamps: Vec<Tickstamp>) -> Archive {
+        let raw_data = RawData {
+            start_time: "1656202584971".to_string(),
+            tickstamps,
+            clock_samples: vec![],
+            discontinuities: vec![],
+        };
+        let ingestion = IngestionMetadata {
+            finalized_at: 1656202600000,
+            server_version: "test".to_string(),
+            dropped_ticks: 0,
+            imported_from: None,
+        };
+        Archive::new(
+            "run",
+            &raw_data,
+            &athlete(),
+            &SummaryOptions::default(),
+            ingestion,
+        )
+    }
+
+    #[test]
+    fn archive_round_trip() {
+        let archive = test_archive(vec![1200, 1230, 1261, 1290]);
+        assert_eq!(archive.tick_deltas, vec![1200, 30, 31, 29]);
+        let bytes = archive.encode().unwrap();
+        assert!(bytes.starts_with(&GZIP_MAGIC));
+        let decoded = Archive::decode("run", &bytes).unwrap();
+        assert_eq!(decoded, archive);
+        assert_eq!(
+            decoded.raw_data().unwrap().tickstamps,
+            vec![1200, 1230, 1261, 1290]
+        );
+    }
+
+    #[test]
+    fn archive_records_pauses() {
+        // a minute at 6 mph, half a minute standing, then another minute
+        let ticks: Vec<Tickstamp> = (0..300)
+            .map(|i| 5000 + 200 * i)
+            .chain((0..300).map(|i| 95_000 + 200 * i))
+            .collect();
+        let archive = test_archive(ticks);
+        assert_eq!(
+            archive.pauses,
+            vec![PauseWindow {
+                start: 64_800,
+                end: 95_000,
+            }]
+        );
+        assert_eq!(test_archive(vec![1200, 1230, 1261, 1290]).pauses, vec![]);
+    }
+
+    #[test]
+    fn archive_compresses_regular_ticks() {
+        let ticks: Vec<Tickstamp> = (1..20_000).map(|t| 30 * t).collect();
+        let legacy = format!("{{\"startTime\":\"1\",\"ticks\":{:?}}}", ticks);
+        let archive = test_archive(ticks);
+        assert!(archive.encode().unwrap().len() * 20 < legacy.len());
+    }
+
+    #[test]
+    fn anonymize_keeps_ticks() {
+        let mut archive = test_archive(vec![1200, 1230, 1261, 1290]);
+        archive.start_time = "1700000000000".to_string();
+        archive.clock_samples = vec![ClockSample {
+            device_time: 1290,
+            wall_time: 1700000000090,
+        }];
+        let original = archive.raw_data().unwrap();
+        archive.anonymize("steady-real");
+        assert_eq!(archive.run_id, "steady-real");
+        assert_eq!(archive.start_time, ANONYMOUS_START.to_string());
+        assert_eq!(archive.athlete.as_ref().unwrap().athlete_id, "anonymous");
+        assert_eq!(archive.athlete.as_ref().unwrap().weight, athlete().weight);
+        assert_eq!(archive.clock_samples[0].wall_time, ANONYMOUS_START + 90);
+        assert_eq!(archive.raw_data().unwrap().tickstamps, original.tickstamps);
+    }
+
+    #[test]
+    fn decode_legacy_archive() {
+        let bytes = b"{\"startTime\":\"123456\",\"ticks\":[6,19,40,100]}";
+        let archive = Archive::decode("run", bytes).unwrap();
+        assert_eq!(archive.version, 1);
+        assert_eq!(archive.athlete, None);
+        let raw_data = archive.raw_data().unwrap();
+        assert_eq!(raw_data.start_time, "123456");
+        assert_eq!(raw_data.tickstamps, vec![6, 19, 40, 100]);
+    }
+
+    #[test]
+    fn decode_fail() {
+        assert!(Archive::decode("run", b"{\"ticks\": [1]}").is_err());
+        assert!(Archive::decode("run", &[0x1f, 0x8b, 0, 0]).is_err());
+    }
+}
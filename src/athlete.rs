@@ -0,0 +1,176 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::constants::KILOMETERS_PER_MILE;
+
+pub const DEFAULT_WEIGHT: f32 = 192.0; // in pounds
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Units {
+    #[default]
+    #[serde(rename = "imperial")]
+    Imperial,
+    #[serde(rename = "metric")]
+    Metric,
+}
+
+impl Units {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Units::Imperial => "imperial",
+            Units::Metric => "metric",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Units> {
+        match name {
+            "imperial" => Some(Units::Imperial),
+            "metric" => Some(Units::Metric),
+            _ => None,
+        }
+    }
+
+    // in miles, the distance of one lap or split in these units
+    pub fn split_distance(&self) -> f32 {
+        match self {
+            Units::Imperial => 1.,
+            Units::Metric => 1. / KILOMETERS_PER_MILE,
+        }
+    }
+
+    // distances are kept in miles and speeds in mph, this converts either for display
+    pub fn convert(&self, miles: f32) -> f32 {
+        match self {
+            Units::Imperial => miles,
+            Units::Metric => miles * KILOMETERS_PER_MILE,
+        }
+    }
+
+    pub fn distance_suffix(&self) -> &'static str {
+        match self {
+            Units::Imperial => "Mi",
+            Units::Metric => "Km",
+        }
+    }
+
+    pub fn speed_suffix(&self) -> &'static str {
+        match self {
+            Units::Imperial => "Mph",
+            Units::Metric => "Kph",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum InvalidAthleteError {
+    MissingName,
+    NonPositiveWeight,
+    NonPositiveRecordDistance(String),
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Athlete {
+    #[serde(rename = "athleteId")]
+    pub id: String,
+    #[serde(rename = "name")]
+    pub name: String,
+    #[serde(rename = "weight")]
+    pub weight: f32, // in pounds
+    #[serde(rename = "units")]
+    pub units: Units,
+    #[serde(rename = "recordDistances")]
+    pub record_distances: HashMap<String, f32>, // in miles
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NewAthlete {
+    #[serde(rename = "name")]
+    pub name: String,
+    #[serde(rename = "weight", default = "default_weight")]
+    pub weight: f32,
+    #[serde(rename = "units", default)]
+    pub units: Units,
+    #[serde(rename = "recordDistances", default = "default_record_distances")]
+    pub record_distances: HashMap<String, f32>,
+}
+
+impl Athlete {
+    pub fn new(id: String, new_athlete: NewAthlete) -> Result<Athlete, InvalidAthleteError> {
+        if new_athlete.name.trim().is_empty() {
+            return Err(InvalidAthleteError::MissingName);
+        }
+        if new_athlete.weight <= 0. {
+            return Err(InvalidAthleteError::NonPositiveWeight);
+        }
+        if let Some((name, _)) = new_athlete.record_distances.iter().find(|(_, d)| **d <= 0.) {
+            return Err(InvalidAthleteError::NonPositiveRecordDistance(
+                name.to_string(),
+            ));
+        }
+        Ok(Athlete {
+            id,
+            name: new_athlete.name.trim().to_string(),
+            weight: new_athlete.weight,
+            units: new_athlete.units,
+            record_distances: new_athlete.record_distances,
+        })
+    }
+}
+
+fn default_weight() -> f32 {
+    DEFAULT_WEIGHT
+}
+
+pub fn default_record_distances() -> HashMap<String, f32> {
+    [
+        ("oneMile", 1.),
+        ("fiveKm", 5. * KILOMETERS_PER_MILE),
+        ("fiveMiles", 5.),
+        ("fourMiles", 4.),
+        ("halfMile", 0.5),
+        ("lap", 0.25),
+        ("oneKm", KILOMETERS_PER_MILE),
+        ("tenKm", 10. * KILOMETERS_PER_MILE),
+        ("threeMiles", 3.),
+        ("twoMiles", 2.),
+    ]
+    .into_iter()
+    .map(|(name, distance)| (name.to_string(), distance))
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_athlete(name: &str) -> NewAthlete {
+        NewAthlete {
+            name: name.to_string(),
+            weight: DEFAULT_WEIGHT,
+            units: Units::Metric,
+            record_distances: default_record_distances(),
+        }
+    }
+
+    #[test]
+    fn new_athlete_success() {
+        let athlete = Athlete::new("abc".to_string(), new_athlete(" Pat ")).unwrap();
+        assert_eq!(athlete.name, "Pat");
+        assert_eq!(athlete.units, Units::Metric);
+        assert_eq!(athlete.record_distances.len(), 10);
+    }
+
+    #[test]
+    fn new_athlete_fail() {
+        let err = Athlete::new("abc".to_string(), new_athlete("  ")).unwrap_err();
+        assert_eq!(err, InvalidAthleteError::MissingName);
+
+        let mut athlete = new_athlete("Pat");
+        athlete.record_distances.insert("nothing".to_string(), 0.);
+        let err = Athlete::new("abc".to_string(), athlete).unwrap_err();
+        assert_eq!(
+            err,
+            InvalidAthleteError::NonPositiveRecordDistance("nothing".to_string())
+        );
+    }
+}
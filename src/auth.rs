@@ -0,0 +1,203 @@
+use rocket::{
+    http::Status,
+    request::{FromRequest, Outcome, Request},
+};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+use uuid::Uuid;
+
+use crate::{aws::fetch_api_key, config};
+
+pub const API_KEY_HEADER: &str = "X-Api-Key";
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum KeyScope {
+    Device,       // may start runs, post ticks and finish them
+    User(String), // may read the athlete's own data
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ApiKey {
+    pub key_hash: String,
+    pub name: String,
+    pub scope: KeyScope,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum AuthError {
+    MissingKey,
+    InvalidKey,
+    WrongScope,
+    Unavailable(String),
+}
+
+// keys are only ever stored as their hash; the plain key is handed out once when issued
+pub fn hash_key(key: &str) -> String {
+    Sha256::digest(key.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+pub fn generate_key() -> String {
+    format!("rd_{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+impl ApiKey {
+    pub fn issue(name: &str, scope: KeyScope) -> (String, ApiKey) {
+        let key = generate_key();
+        let api_key = ApiKey {
+            key_hash: hash_key(&key),
+            name: name.to_string(),
+            scope,
+        };
+        (key, api_key)
+    }
+}
+
+fn presented_key<'r>(req: &'r Request<'_>) -> Result<&'r str, AuthError> {
+    match req.headers().get_one(API_KEY_HEADER) {
+        Some(key) if !key.trim().is_empty() => Ok(key.trim()),
+        _ => Err(AuthError::MissingKey),
+    }
+}
+
+fn presented_key_hash(req: &Request<'_>) -> Result<String, AuthError> {
+    presented_key(req).map(hash_key)
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+// compared in constant time, so that response times don't give the hash away
+fn is_admin_key(key: &str, admin_hash: &str) -> bool {
+    match decode_hex(admin_hash.trim()) {
+        Some(admin_digest) => Sha256::digest(key.as_bytes())
+            .as_slice()
+            .ct_eq(&admin_digest)
+            .into(),
+        None => false,
+    }
+}
+
+async fn authenticate(req: &Request<'_>) -> Result<ApiKey, AuthError> {
+    let key_hash = presented_key_hash(req)?;
+    match fetch_api_key(&key_hash).await {
+        Ok(Some(api_key)) => Ok(api_key),
+        Ok(None) => Err(AuthError::InvalidKey),
+        Err(e) => Err(AuthError::Unavailable(e.msg)),
+    }
+}
+
+fn failure<T>(e: AuthError) -> Outcome<T, AuthError> {
+    let status = match e {
+        AuthError::MissingKey | AuthError::InvalidKey => Status::Unauthorized,
+        AuthError::WrongScope => Status::Forbidden,
+        AuthError::Unavailable(_) => Status::ServiceUnavailable,
+    };
+    Outcome::Error((status, e))
+}
+
+// an ingestion key belonging to a treadmill
+pub struct DeviceKey {
+    pub key_hash: String,
+}
+
+// a read key belonging to a single athlete
+pub struct UserKey {
+    pub athlete_id: String,
+}
+
+// the operator key, configured as a hash through ADMIN_API_KEY_HASH
+pub struct AdminKey;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for DeviceKey {
+    type Error = AuthError;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match authenticate(req).await {
+            Ok(ApiKey {
+                key_hash,
+                scope: KeyScope::Device,
+                ..
+            }) => Outcome::Success(DeviceKey { key_hash }),
+            Ok(_) => failure(AuthError::WrongScope),
+            Err(e) => failure(e),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for UserKey {
+    type Error = AuthError;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match authenticate(req).await {
+            Ok(ApiKey {
+                scope: KeyScope::User(athlete_id),
+                ..
+            }) => Outcome::Success(UserKey { athlete_id }),
+            Ok(_) => failure(AuthError::WrongScope),
+            Err(e) => failure(e),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AdminKey {
+    type Error = AuthError;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let key = match presented_key(req) {
+            Ok(k) => k,
+            Err(e) => return failure(e),
+        };
+        match &config::get().admin_api_key_hash {
+            Some(admin_hash) if is_admin_key(key, admin_hash) => Outcome::Success(AdminKey),
+            Some(_) => failure(AuthError::InvalidKey),
+            None => failure(AuthError::Unavailable(
+                "no admin key is configured".to_string(),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hash_key_success() {
+        assert_eq!(
+            hash_key("abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn admin_key_matches_its_hash() {
+        let hash = hash_key("admin");
+        assert!(is_admin_key("admin", &hash));
+        assert!(is_admin_key("admin", &format!(" {} ", hash.to_uppercase())));
+        assert!(!is_admin_key("admin2", &hash));
+        assert!(!is_admin_key("admin", &hash[..62]));
+        assert!(!is_admin_key("admin", "not hex"));
+    }
+
+    #[test]
+    fn issue_key_success() {
+        let (key, api_key) = ApiKey::issue("treadmill", KeyScope::Device);
+        assert!(key.starts_with("rd_"));
+        assert_eq!(key.len(), 67);
+        assert_eq!(api_key.key_hash, hash_key(&key));
+        assert_ne!(ApiKey::issue("treadmill", KeyScope::Device).0, key);
+    }
+}
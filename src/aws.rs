@@ -1,14 +1,35 @@
 use aws_sdk_dynamodb::{
     model::AttributeValue,
-    model::AttributeValue::{M, N, S},
+    model::AttributeValue::{L, M, N, S},
+    types::SdkError,
     Client as DynamoClient,
 };
-use aws_sdk_s3::{types::ByteStream as S3BytesStream, Client as S3Client};
-use json::{stringify, JsonValue};
-use std::{collections::HashMap, env, fs::File, io::Write, string::String};
+use aws_sdk_s3::{
+    model::{CompletedMultipartUpload, CompletedPart},
+    types::ByteStream as S3BytesStream,
+    Client as S3Client,
+};
+use std::{collections::HashMap, string::String};
+
 
+use crate::{
+    archive::Archive,
+    athlete::{Athlete, Units},
+    auth::{ApiKey, KeyScope},
+    clock::ClockSync,
+    config,
+    records::{PersonalBest, PersonalRecords, RecordHistory, RecordWindow},
+    timeline::Discontinuity,
+    run::{
+        self, DebounceStats, DistanceRecord, DistanceRecordSet, LargestRect, RunListing, Summary,
+        Timestamp,
+    },
+};
 
-use crate::run::{self, DistanceRecord, DistanceRecordSet, LargestRect, Summary};
+const SUMMARY_ATHLETE_INDEX: &str = "athleteId-startTime-index";
+const MULTIPART_THRESHOLD: usize = 16 * 1024 * 1024; // in bytes
+const MULTIPART_PART_SIZE: usize = 8 * 1024 * 1024; // in bytes, s3 requires at least 5MiB
+const RECORDS_WRITE_ATTEMPTS: usize = 5; // reads and writes of records racing other writers
 
 
 pub async fn push_summary_to_db(summary: &Summary<'_>) -> Result<(), AwsError> {
@@ -16,138 +37,752 @@ pub async fn push_summary_to_db(summary: &Summary<'_>) -> Result<(), AwsError> {
     let client = DynamoClient::new(&shared_config);
     let mut req = client
         .put_item()
-        .table_name(env::var("AWS_DYNAMO_TABLE_SUMMARY").unwrap());
+        .table_name(&config::get().aws.summary_table);
     for (k, v) in summary.attributes() {
         req = req.item(k, v);
     }
     match req.send().await {
         Ok(_) => Ok(()),
         Err(e) => Err(AwsError {
-            msg: format!("error pushing summary to db: {}", e.to_string()),
+            kind: AwsErrorKind::Unavailable,
+            msg: format!("error pushing summary to db: {}", e),
         }),
     }
 }
 
-pub async fn write_data_to_s3(run_id: &str, data: JsonValue) -> Result<(), AwsError> {
+pub async fn summary_exists(run_id: &str) -> Result<bool, AwsError> {
     let shared_config = aws_config::load_from_env().await;
-    let client = S3Client::new(&shared_config);
+    let client = DynamoClient::new(&shared_config);
+    let req = client
+        .get_item()
+        .table_name(&config::get().aws.summary_table)
+        .key("runId", S(run_id.to_string()))
+        .projection_expression("runId");
+    match req.send().await {
+        Ok(res) => Ok(res.item().is_some()),
+        Err(e) => Err(AwsError {
+            kind: AwsErrorKind::Unavailable,
+            msg: format!("error fetching summary from db: {}", e),
+        }),
+    }
+}
 
-    let filepath = match write_file(run_id, data) {
-        Ok(f) => f,
-        Err(e) => {
-            return Err(AwsError {
-                msg: format!("unable to write file: {}", e.to_string()),
-            });
+pub async fn fetch_run(run_id: &str) -> Result<Option<RunListing>, AwsError> {
+    let shared_config = aws_config::load_from_env().await;
+    let client = DynamoClient::new(&shared_config);
+    let req = client
+        .get_item()
+        .table_name(&config::get().aws.summary_table)
+        .key("runId", S(run_id.to_string()));
+    match req.send().await {
+        Ok(res) => Ok(res.item().and_then(RunListing::from_attributes)),
+        Err(e) => Err(AwsError {
+            kind: AwsErrorKind::Unavailable,
+            msg: format!("error fetching summary from db: {}", e),
+        }),
+    }
+}
+
+pub async fn fetch_all_runs() -> Result<Vec<RunListing>, AwsError> {
+    let shared_config = aws_config::load_from_env().await;
+    let client = DynamoClient::new(&shared_config);
+    let mut runs = vec![];
+    let mut start_key = None;
+    loop {
+        let req = client
+            .scan()
+            .table_name(&config::get().aws.summary_table)
+            .set_exclusive_start_key(start_key);
+        let res = match req.send().await {
+            Ok(res) => res,
+            Err(e) => {
+                return Err(AwsError {
+                    kind: AwsErrorKind::Unavailable,
+                    msg: format!("error scanning runs in db: {}", e),
+                })
+            }
+        };
+        if let Some(items) = res.items() {
+            runs.extend(items.iter().filter_map(RunListing::from_attributes));
         }
+        start_key = match res.last_evaluated_key() {
+            Some(key) => Some(key.clone()),
+            None => return Ok(runs),
+        };
+    }
+}
+
+// where the next page of a paginated query starts
+pub type PageKey = HashMap<String, AttributeValue>;
+
+pub async fn fetch_runs_for_athlete(athlete_id: &str) -> Result<Vec<RunListing>, AwsError> {
+    let mut runs = vec![];
+    let mut start_key = None;
+    loop {
+        let (page, next) = fetch_run_page(athlete_id, None, None, start_key).await?;
+        runs.extend(page);
+        start_key = match next {
+            Some(key) => Some(key),
+            None => return Ok(runs),
+        };
+    }
+}
+
+// one page of an athlete's runs, newest first, optionally limited to runs started
+// between from and to (epoch millis, inclusive)
+pub async fn fetch_run_page(
+    athlete_id: &str,
+    from: Option<u64>,
+    to: Option<u64>,
+    start_key: Option<PageKey>,
+) -> Result<(Vec<RunListing>, Option<PageKey>), AwsError> {
+    let shared_config = aws_config::load_from_env().await;
+    let client = DynamoClient::new(&shared_config);
+    let mut req = client
+        .query()
+        .table_name(&config::get().aws.summary_table)
+        .index_name(SUMMARY_ATHLETE_INDEX)
+        .expression_attribute_values(":athleteId", S(athlete_id.to_string()))
+        .scan_index_forward(false)
+        .set_exclusive_start_key(start_key);
+    req = match (from, to) {
+        (None, None) => req.key_condition_expression("athleteId = :athleteId"),
+        (from, to) => req
+            .key_condition_expression("athleteId = :athleteId AND startTime BETWEEN :from AND :to")
+            .expression_attribute_values(":from", N(from.unwrap_or(0).to_string()))
+            .expression_attribute_values(":to", N(to.unwrap_or(u64::MAX).to_string())),
     };
-    let bytestream = match S3BytesStream::read_from()
-        .path(filepath.as_str())
-        .build()
-        .await
-    {
-        Ok(bs) => bs,
+    let res = match req.send().await {
+        Ok(res) => res,
         Err(e) => {
             return Err(AwsError {
-                msg: format!("error reading bytestream: {}", e.to_string()),
+                kind: AwsErrorKind::Unavailable,
+                msg: format!("error fetching runs from db: {}", e),
             })
         }
     };
+    let runs = match res.items() {
+        Some(items) => items.iter().filter_map(RunListing::from_attributes).collect(),
+        None => vec![],
+    };
+    Ok((runs, res.last_evaluated_key().cloned()))
+}
+
+pub async fn push_athlete_to_db(athlete: &Athlete) -> Result<(), AwsError> {
+    let shared_config = aws_config::load_from_env().await;
+    let client = DynamoClient::new(&shared_config);
+    let mut req = client
+        .put_item()
+        .table_name(&config::get().aws.athletes_table);
+    for (k, v) in athlete.attributes() {
+        req = req.item(k, v);
+    }
+    match req.send().await {
+        Ok(_) => Ok(()),
+        Err(e) => Err(AwsError {
+            kind: AwsErrorKind::Unavailable,
+            msg: format!("error pushing athlete to db: {}", e),
+        }),
+    }
+}
+
+pub async fn fetch_athlete(athlete_id: &str) -> Result<Option<Athlete>, AwsError> {
+    let shared_config = aws_config::load_from_env().await;
+    let client = DynamoClient::new(&shared_config);
     let req = client
-        .put_object()
-        .bucket(env::var("AWS_S3_RAW_DATA_BUCKET").unwrap())
-        .body(bytestream)
-        .key(run_id);
+        .get_item()
+        .table_name(&config::get().aws.athletes_table)
+        .key("athleteId", S(athlete_id.to_string()));
+    match req.send().await {
+        Ok(res) => Ok(res.item().and_then(Athlete::from_attributes)),
+        Err(e) => Err(AwsError {
+            kind: AwsErrorKind::Unavailable,
+            msg: format!("error fetching athlete from db: {}", e),
+        }),
+    }
+}
+
+pub async fn push_api_key(api_key: &ApiKey) -> Result<(), AwsError> {
+    let shared_config = aws_config::load_from_env().await;
+    let client = DynamoClient::new(&shared_config);
+    let mut req = client
+        .put_item()
+        .table_name(&config::get().aws.api_keys_table);
+    for (k, v) in api_key.attributes() {
+        req = req.item(k, v);
+    }
+    match req.send().await {
+        Ok(_) => Ok(()),
+        Err(e) => Err(AwsError {
+            kind: AwsErrorKind::Unavailable,
+            msg: format!("error pushing api key to db: {}", e),
+        }),
+    }
+}
+
+pub async fn fetch_api_key(key_hash: &str) -> Result<Option<ApiKey>, AwsError> {
+    let shared_config = aws_config::load_from_env().await;
+    let client = DynamoClient::new(&shared_config);
+    let req = client
+        .get_item()
+        .table_name(&config::get().aws.api_keys_table)
+        .key("keyHash", S(key_hash.to_string()));
+    match req.send().await {
+        Ok(res) => Ok(res.item().and_then(ApiKey::from_attributes)),
+        Err(e) => Err(AwsError {
+            kind: AwsErrorKind::Unavailable,
+            msg: format!("error fetching api key from db: {}", e),
+        }),
+    }
+}
+
+// the athlete's records and the version they were read at, 0 when there are none
+// yet or they were stored before records had versions
+pub async fn fetch_personal_records(athlete_id: &str) -> Result<(PersonalRecords, u64), AwsError> {
+    let shared_config = aws_config::load_from_env().await;
+    let client = DynamoClient::new(&shared_config);
+    let req = client
+        .get_item()
+        .table_name(&config::get().aws.records_table)
+        .key("athleteId", S(athlete_id.to_string()));
+    match req.send().await {
+        Ok(res) => {
+            let item = res.item();
+            let records = item
+                .and_then(|item| item.get("records"))
+                .map_or_else(PersonalRecords::default, PersonalRecords::from_attribute);
+            let version = item
+                .and_then(|item| item.get("version")?.as_n().ok()?.parse().ok())
+                .unwrap_or_default();
+            Ok((records, version))
+        }
+        Err(e) => Err(AwsError {
+            kind: AwsErrorKind::Unavailable,
+            msg: format!("error fetching personal records from db: {}", e),
+        }),
+    }
+}
 
+// stores the records as the next version, unless another write got in since they
+// were read at version; returns whether they were stored
+pub async fn push_personal_records(
+    athlete_id: &str,
+    records: &PersonalRecords,
+    version: u64,
+) -> Result<bool, AwsError> {
+    let shared_config = aws_config::load_from_env().await;
+    let client = DynamoClient::new(&shared_config);
+    let req = client
+        .put_item()
+        .table_name(&config::get().aws.records_table)
+        .item("athleteId", S(athlete_id.to_string()))
+        .item("records", records.to_attribute())
+        .item("version", N((version + 1).to_string()))
+        .condition_expression("attribute_not_exists(#version) OR #version = :version")
+        .expression_attribute_names("#version", "version")
+        .expression_attribute_values(":version", N(version.to_string()));
     match req.send().await {
-        Ok(_) => match std::fs::remove_file(filepath) {
+        Ok(_) => Ok(true),
+        Err(SdkError::ServiceError { err, .. }) if err.is_conditional_check_failed_exception() => {
+            Ok(false)
+        }
+        Err(e) => Err(AwsError {
+            kind: AwsErrorKind::Unavailable,
+            msg: format!("error pushing personal records to db: {}", e),
+        }),
+    }
+}
+
+// folds the summary into the athlete's records, reading them again whenever another
+// run's write got in first; returns the windows the run is a new best in
+pub async fn update_personal_records(
+    athlete_id: &str,
+    summary: &Summary<'_>,
+) -> Result<HashMap<String, Vec<RecordWindow>>, AwsError> {
+    for _ in 0..RECORDS_WRITE_ATTEMPTS {
+        let (mut records, version) = fetch_personal_records(athlete_id).await?;
+        let new_personal_bests = records.update(summary);
+        if push_personal_records(athlete_id, &records, version).await? {
+            return Ok(new_personal_bests);
+        }
+    }
+    Err(AwsError {
+        kind: AwsErrorKind::Unavailable,
+        msg: format!(
+            "personal records of athlete {} kept changing while being updated",
+            athlete_id
+        ),
+    })
+}
+
+pub async fn read_archive_from_s3(run_id: &str) -> Result<Archive, AwsError> {
+    let data = read_data_from_s3(run_id).await?;
+    Archive::decode(run_id, &data).map_err(|e| AwsError {
+        kind: AwsErrorKind::Unavailable,
+        msg: format!("error reading archive of run {}: {}", run_id, e.msg),
+    })
+}
+
+async fn read_data_from_s3(run_id: &str) -> Result<Vec<u8>, AwsError> {
+    let shared_config = aws_config::load_from_env().await;
+    let client = S3Client::new(&shared_config);
+    let req = client
+        .get_object()
+        .bucket(&config::get().aws.raw_data_bucket)
+        .key(run_id);
+    let res = match req.send().await {
+        Ok(res) => res,
+        Err(SdkError::ServiceError { err, .. }) if err.is_no_such_key() => {
+            return Err(AwsError {
+                kind: AwsErrorKind::NotFound,
+                msg: format!("no data stored for run {}", run_id),
+            })
+        }
+        Err(e) => {
+            return Err(AwsError {
+                kind: AwsErrorKind::Unavailable,
+                msg: format!("error reading data from s3: {}", e),
+            })
+        }
+    };
+    match res.body.collect().await {
+        Ok(b) => Ok(b.into_bytes().to_vec()),
+        Err(e) => Err(AwsError {
+            kind: AwsErrorKind::Unavailable,
+            msg: format!("error reading bytestream: {}", e),
+        }),
+    }
+}
+
+// uploads straight from memory; archives too large for a single put go up in parts
+pub async fn write_data_to_s3(key: &str, body: Vec<u8>) -> Result<(), AwsError> {
+    let shared_config = aws_config::load_from_env().await;
+    let client = S3Client::new(&shared_config);
+    let bucket = &config::get().aws.raw_data_bucket;
+
+    if body.len() <= MULTIPART_THRESHOLD {
+        let req = client
+            .put_object()
+            .bucket(bucket)
+            .body(S3BytesStream::from(body))
+            .key(key);
+        return match req.send().await {
             Ok(_) => Ok(()),
             Err(e) => Err(AwsError {
-                msg: format!("error deleting local file after upload: {}", e.to_string()),
+                kind: AwsErrorKind::Unavailable,
+                msg: format!("error writing data to s3: {}", e),
             }),
-        },
+        };
+    }
+
+    let upload = match client
+        .create_multipart_upload()
+        .bucket(bucket)
+        .key(key)
+        .send()
+        .await
+    {
+        Ok(res) => res,
+        Err(e) => {
+            return Err(AwsError {
+                kind: AwsErrorKind::Unavailable,
+                msg: format!("error starting multipart upload to s3: {}", e),
+            })
+        }
+    };
+    let upload_id = match upload.upload_id() {
+        Some(id) if !id.is_empty() => id,
+        _ => {
+            return Err(AwsError {
+                kind: AwsErrorKind::Unavailable,
+                msg: "s3 started a multipart upload without an upload id".to_string(),
+            })
+        }
+    };
+    match upload_parts(&client, bucket, key, upload_id, body).await {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            // parts of an abandoned upload are billed until aborted
+            let _ = client
+                .abort_multipart_upload()
+                .bucket(bucket)
+                .key(key)
+                .upload_id(upload_id)
+                .send()
+                .await;
+            Err(e)
+        }
+    }
+}
+
+async fn upload_parts(
+    client: &S3Client,
+    bucket: &str,
+    key: &str,
+    upload_id: &str,
+    body: Vec<u8>,
+) -> Result<(), AwsError> {
+    let mut completed = CompletedMultipartUpload::builder();
+    for (i, chunk) in body.chunks(MULTIPART_PART_SIZE).enumerate() {
+        let part_number = i as i32 + 1;
+        let res = match client
+            .upload_part()
+            .bucket(bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .part_number(part_number)
+            .body(S3BytesStream::from(chunk.to_vec()))
+            .send()
+            .await
+        {
+            Ok(res) => res,
+            Err(e) => {
+                return Err(AwsError {
+                    kind: AwsErrorKind::Unavailable,
+                    msg: format!("error writing part {} to s3: {}", part_number, e),
+                })
+            }
+        };
+        completed = completed.parts(
+            CompletedPart::builder()
+                .set_e_tag(res.e_tag().map(|t| t.to_string()))
+                .part_number(part_number)
+                .build(),
+        );
+    }
+    match client
+        .complete_multipart_upload()
+        .bucket(bucket)
+        .key(key)
+        .upload_id(upload_id)
+        .multipart_upload(completed.build())
+        .send()
+        .await
+    {
+        Ok(_) => Ok(()),
         Err(e) => Err(AwsError {
-            msg: format!("error writing data to s3: {}", e.to_string()),
+            kind: AwsErrorKind::Unavailable,
+            msg: format!("error completing multipart upload to s3: {}", e),
         }),
     }
 }
 
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum AwsErrorKind {
+    NotFound, // there is nothing stored under the key, retrying won't change that
+    Unavailable,
+}
+
 #[derive(Debug)]
 pub struct AwsError {
+pub kind: AwsErrorKind,
 pub msg: String,
 }
 
-impl<'a> DistanceRecordSet<'a> {
+impl DistanceRecordSet {
 fn to_hash_attribute(&self) -> HashMap<String, AttributeValue> {
     self.0
         .iter()
         .filter_map(|(k, v)| v.as_ref().map(|dr| (k.to_string(), dr.to_attribute())))
         .collect()
 }
+
+fn from_hash_attribute(item: &HashMap<String, AttributeValue>) -> DistanceRecordSet {
+    DistanceRecordSet(
+        item.iter()
+            .map(|(k, v)| (k.to_string(), DistanceRecord::from_attribute(v)))
+            .collect(),
+    )
+}
+}
+
+// times are stored in millis under <key>Ms; items written before that have
+// whole seconds under <key>
+fn millis(item: &HashMap<String, AttributeValue>, key: &str) -> Option<Timestamp> {
+    match item.get(&format!("{}Ms", key)) {
+        Some(ms) => ms.as_n().ok()?.parse().ok(),
+        None => item
+            .get(key)?
+            .as_n()
+            .ok()?
+            .parse::<Timestamp>()
+            .ok()?
+            .checked_mul(1000),
+    }
 }
 
 impl DistanceRecord {
 fn to_attribute(&self) -> AttributeValue {
     let mut res = HashMap::new();
-    res.insert("left".to_string(), N(self.start_time.to_string()));
+    res.insert("leftMs".to_string(), N(self.start_time.to_string()));
     res.insert("leftD".to_string(), N(self.start_distance.to_string()));
-    res.insert("right".to_string(), N(self.end_time.to_string()));
+    res.insert("rightMs".to_string(), N(self.end_time.to_string()));
     res.insert("rightD".to_string(), N(self.end_distance.to_string()));
-    res.insert("time".to_string(), N(self.time.to_string()));
+    res.insert("timeMs".to_string(), N(self.time.to_string()));
     M(res)
 }
+
+fn from_attribute(attribute: &AttributeValue) -> Option<DistanceRecord> {
+    let item = attribute.as_m().ok()?;
+    Some(DistanceRecord {
+        start_time: millis(item, "left")?,
+        end_time: millis(item, "right")?,
+        start_distance: item.get("leftD")?.as_n().ok()?.parse().ok()?,
+        end_distance: item.get("rightD")?.as_n().ok()?.parse().ok()?,
+        time: millis(item, "time")?,
+    })
+}
 }
 
 impl LargestRect {
 fn to_hash_attribute(&self) -> HashMap<String, AttributeValue> {
     HashMap::from([
-        ("start".to_string(), N(self.start_time.to_string())),
-        ("end".to_string(), N(self.end_time.to_string())),
+        ("startMs".to_string(), N(self.start_time.to_string())),
+        ("endMs".to_string(), N(self.end_time.to_string())),
         ("height".to_string(), N(self.height.to_string())),
         ("area".to_string(), N(self.area.to_string())),
     ])
 }
 }
 
+impl DebounceStats {
+fn to_hash_attribute(&self) -> HashMap<String, AttributeValue> {
+    HashMap::from([
+        ("droppedTicks".to_string(), N(self.dropped_ticks.to_string())),
+        ("bounceRate".to_string(), N(self.bounce_rate.to_string())),
+    ])
+}
+}
+
+impl ClockSync {
+fn to_hash_attribute(self) -> HashMap<String, AttributeValue> {
+    HashMap::from([
+        ("start".to_string(), N(self.start.to_string())),
+        ("driftPpm".to_string(), N(self.drift_ppm.to_string())),
+        ("samples".to_string(), N(self.samples.to_string())),
+    ])
+}
+}
+
+impl Discontinuity {
+fn to_attribute(self) -> AttributeValue {
+    M(HashMap::from([
+        ("kind".to_string(), S(self.kind.name().to_string())),
+        ("timeMs".to_string(), N(self.time.to_string())),
+    ]))
+}
+}
+
 impl<'a> Summary<'a> {
 fn attributes(&self) -> HashMap<&str, AttributeValue> {
-    HashMap::from([
+    let mut attributes = HashMap::from([
         ("runId", S(self.id.to_string())),
-        ("totalTime", N(self.total_time.to_string())),
+        ("athleteId", S(self.athlete_id.clone())),
+        ("totalTime", N(self.total_time_secs.to_string())),
         ("startTime", N(self.start_time.clone())),
         ("totalCalories", N(self.total_calories.to_string())),
         ("totalDistance", N(self.total_distance.to_string())),
         ("maxRectangle", M(self.largest_rect.to_hash_attribute())),
+        ("debounce", M(self.debounce.to_hash_attribute())),
         (
             "bestDistances",
             M(self.distance_records.to_hash_attribute()),
         ),
+        (
+            "newPersonalBests",
+            M(self
+                .new_personal_bests
+                .iter()
+                .map(|(k, windows)| {
+                    let windows = windows.iter().map(|w| S(w.name().to_string())).collect();
+                    (k.to_string(), L(windows))
+                })
+                .collect()),
+        ),
+    ]);
+    // only runs whose device sent its clock have one
+    if let Some(clock_sync) = &self.clock_sync {
+        attributes.insert("clockSync", M(clock_sync.to_hash_attribute()));
+    }
+    if !self.discontinuities.is_empty() {
+        let discontinuities = self.discontinuities.iter().map(|d| d.to_attribute()).collect();
+        attributes.insert("discontinuities", L(discontinuities));
+    }
+    attributes
+}
+}
+
+impl RunListing {
+fn from_attributes(item: &HashMap<String, AttributeValue>) -> Option<RunListing> {
+    Some(RunListing {
+        id: item.get("runId")?.as_s().ok()?.clone(),
+        start_time: item.get("startTime")?.as_n().ok()?.clone(),
+        total_time_secs: item.get("totalTime")?.as_n().ok()?.parse().ok()?,
+        total_calories: item.get("totalCalories")?.as_n().ok()?.parse().ok()?,
+        total_distance: item.get("totalDistance")?.as_n().ok()?.parse().ok()?,
+        athlete_id: match item.get("athleteId") {
+            Some(a) => a.as_s().ok()?.clone(),
+            None => String::new(),
+        },
+        distance_records: match item.get("bestDistances").map(|r| r.as_m()) {
+            Some(Ok(records)) => DistanceRecordSet::from_hash_attribute(records),
+            _ => DistanceRecordSet(HashMap::new()),
+        },
+        new_personal_bests: match item.get("newPersonalBests").map(|pbs| pbs.as_m()) {
+            Some(Ok(pbs)) => pbs
+                .iter()
+                .map(|(k, windows)| {
+                    let windows = match windows.as_l() {
+                        Ok(l) => l
+                            .iter()
+                            .filter_map(|w| RecordWindow::from_name(w.as_s().ok()?))
+                            .collect(),
+                        Err(_) => vec![],
+                    };
+                    (k.to_string(), windows)
+                })
+                .collect(),
+            _ => HashMap::new(),
+        },
+    })
+}
+}
+
+impl Athlete {
+fn attributes(&self) -> HashMap<&str, AttributeValue> {
+    HashMap::from([
+        ("athleteId", S(self.id.clone())),
+        ("name", S(self.name.clone())),
+        ("weight", N(self.weight.to_string())),
+        ("units", S(self.units.name().to_string())),
+        (
+            "recordDistances",
+            M(self
+                .record_distances
+                .iter()
+                .map(|(k, d)| (k.to_string(), N(d.to_string())))
+                .collect()),
+        ),
     ])
 }
+
+fn from_attributes(item: &HashMap<String, AttributeValue>) -> Option<Athlete> {
+    Some(Athlete {
+        id: item.get("athleteId")?.as_s().ok()?.clone(),
+        name: item.get("name")?.as_s().ok()?.clone(),
+        weight: item.get("weight")?.as_n().ok()?.parse().ok()?,
+        units: Units::from_name(item.get("units")?.as_s().ok()?)?,
+        record_distances: item
+            .get("recordDistances")?
+            .as_m()
+            .ok()?
+            .iter()
+            .filter_map(|(k, d)| Some((k.to_string(), d.as_n().ok()?.parse().ok()?)))
+            .collect(),
+    })
+}
+}
+
+impl ApiKey {
+fn attributes(&self) -> HashMap<&str, AttributeValue> {
+    let mut res = HashMap::from([
+        ("keyHash", S(self.key_hash.clone())),
+        ("name", S(self.name.clone())),
+    ]);
+    match &self.scope {
+        KeyScope::Device => {
+            res.insert("scope", S("device".to_string()));
+        }
+        KeyScope::User(athlete_id) => {
+            res.insert("scope", S("user".to_string()));
+            res.insert("athleteId", S(athlete_id.clone()));
+        }
+    }
+    res
+}
+
+fn from_attributes(item: &HashMap<String, AttributeValue>) -> Option<ApiKey> {
+    let scope = match item.get("scope")?.as_s().ok()?.as_str() {
+        "device" => KeyScope::Device,
+        "user" => KeyScope::User(item.get("athleteId")?.as_s().ok()?.clone()),
+        _ => return None,
+    };
+    Some(ApiKey {
+        key_hash: item.get("keyHash")?.as_s().ok()?.clone(),
+        name: item.get("name")?.as_s().ok()?.clone(),
+        scope,
+    })
+}
+}
+
+impl PersonalBest {
+fn to_attribute(&self) -> AttributeValue {
+    M(HashMap::from([
+        ("runId".to_string(), S(self.run_id.clone())),
+        ("runStart".to_string(), N(self.run_start.to_string())),
+        ("timeMs".to_string(), N(self.time.to_string())),
+    ]))
+}
+
+fn from_attribute(attr: &AttributeValue) -> Option<PersonalBest> {
+    let m = attr.as_m().ok()?;
+    Some(PersonalBest {
+        run_id: m.get("runId")?.as_s().ok()?.clone(),
+        run_start: m.get("runStart")?.as_n().ok()?.parse().ok()?,
+        time: millis(m, "time")?,
+    })
+}
+}
+
+impl PersonalRecords {
+fn to_attribute(&self) -> AttributeValue {
+    M(self
+        .0
+        .iter()
+        .map(|(name, history)| {
+            let mut res = HashMap::new();
+            if let Some(pb) = &history.all_time {
+                res.insert("allTime".to_string(), pb.to_attribute());
+            }
+            res.insert(
+                "recent".to_string(),
+                L(history.recent.iter().map(|pb| pb.to_attribute()).collect()),
+            );
+            (name.to_string(), M(res))
+        })
+        .collect())
+}
+
+fn from_attribute(attr: &AttributeValue) -> PersonalRecords {
+    let m = match attr.as_m() {
+        Ok(m) => m,
+        Err(_) => return PersonalRecords::default(),
+    };
+    PersonalRecords(
+        m.iter()
+            .filter_map(|(name, history)| {
+                let history = history.as_m().ok()?;
+                let all_time = history.get("allTime").and_then(PersonalBest::from_attribute);
+                let recent = match history.get("recent").map(|r| r.as_l()) {
+                    Some(Ok(l)) => l.iter().filter_map(PersonalBest::from_attribute).collect(),
+                    _ => vec![],
+                };
+                Some((name.to_string(), RecordHistory { all_time, recent }))
+            })
+            .collect(),
+    )
+}
 }
 
 impl From<run::DistanceRecord> for HashMap<&str, AttributeValue> {
     fn from(item: run::DistanceRecord) -> Self {
         let time = item.end_time - item.start_time;
         HashMap::from([
-            ("left", N(item.start_time.to_string())),
-            ("right", N(item.end_time.to_string())),
+            ("leftMs", N(item.start_time.to_string())),
+            ("rightMs", N(item.end_time.to_string())),
             ("leftD", N(item.start_distance.to_string())),
             ("rightD", N(item.end_distance.to_string())),
-            ("time", N(time.to_string())),
+            ("timeMs", N(time.to_string())),
         ])
     }
 }
-
-fn write_file<T>(run_id: &str, data: T) -> Result<String, std::io::Error>
-where
-    T: Into<JsonValue>,
-{
-    let path = data_file_path(run_id);
-    let mut file = File::create(&path)?;
-    file.write_all(stringify(data).as_bytes())?;
-    Ok(path)
-}
-
-fn data_file_path(run_id: &str) -> String {
-    format!("./tmp/{}.json", run_id)
-}
@@ -0,0 +1,229 @@
+use rocket::serde::json::{self, serde_json::json};
+use rusty_dusty::{
+    archive::Archive,
+    athlete::{default_record_distances, Athlete, DEFAULT_WEIGHT},
+    csv::record_names,
+    export::iso8601,
+    filter::SpeedFilterConfig,
+    run::{RawData, Summary, SummaryOptions, Tickstamp, Timestamp},
+};
+use std::{
+    env, fs,
+    io::{self, Read},
+    process,
+};
+
+const USAGE: &str = "usage: rusty-dusty-analyze [options] [file]
+
+Summarizes a raw run archive (as stored in s3) or a plain list of tickstamps,
+read from file or, without one, from stdin.
+
+options:
+  --json                  print the summary as json instead of a table
+  --series                include the per-interval series
+  --ticks-per-mile <n>    treadmill calibration, defaults to the archive's
+  --debounce <ms>         debounce time, defaults to the archive's
+  --fixed-debounce        only drop ticks within the debounce time, not relative to the pace
+  --weight <lbs>          athlete weight, defaults to the archive's
+  --interval <ms>         series resolution, 1000 by default
+  --filter <name>         speed smoothing: ema (default), moving-average, median or kalman";
+
+#[derive(Default)]
+struct Args {
+    file: Option<String>,
+    json: bool,
+    series: bool,
+    ticks_per_mile: Option<f32>,
+    debounce_time: Option<u32>,
+    fixed_debounce: bool,
+    weight: Option<f32>,
+    interval_size: Option<u32>,
+    speed_filter: Option<SpeedFilterConfig>,
+}
+
+fn parse_args(args: &[String]) -> Result<Args, String> {
+    let mut res = Args::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .ok_or_else(|| format!("{} needs a value", name))
+                .map(|v| v.to_string())
+        };
+        match arg.as_str() {
+            "--json" => res.json = true,
+            "--series" => res.series = true,
+            "--fixed-debounce" => res.fixed_debounce = true,
+            "--ticks-per-mile" => res.ticks_per_mile = Some(number(arg, &value(arg)?)?),
+            "--debounce" => res.debounce_time = Some(number(arg, &value(arg)?)?),
+            "--weight" => res.weight = Some(number(arg, &value(arg)?)?),
+            "--interval" => res.interval_size = Some(number(arg, &value(arg)?)?),
+            "--filter" => {
+                let name = value(arg)?;
+                res.speed_filter = Some(
+                    SpeedFilterConfig::from_name(&name)
+                        .ok_or_else(|| format!("unknown filter {:?}", name))?,
+                )
+            }
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            flag if flag.starts_with("--") => return Err(format!("unknown option {}", flag)),
+            file if res.file.is_none() => res.file = Some(file.to_string()),
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+    Ok(res)
+}
+
+fn number<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value for {}: {:?}", name, value))
+}
+
+fn read_input(file: &Option<String>) -> Result<Vec<u8>, String> {
+    let mut bytes = vec![];
+    match file {
+        Some(path) => bytes = fs::read(path).map_err(|e| format!("{}: {}", path, e))?,
+        None => {
+            io::stdin()
+                .read_to_end(&mut bytes)
+                .map_err(|e| format!("error reading stdin: {}", e))?;
+        }
+    }
+    Ok(bytes)
+}
+
+// tickstamps separated by commas or whitespace, as posted by devices, optionally
+// wrapped in brackets
+fn parse_tick_list(text: &str) -> Result<Vec<Tickstamp>, String> {
+    text.split(|c: char| c == ',' || c == '[' || c == ']' || c.is_whitespace())
+        .filter(|t| !t.is_empty())
+        .map(|t| number("tickstamp", t))
+        .collect()
+}
+
+fn seconds(time: Timestamp) -> f32 {
+    time as f32 / 1000.
+}
+
+fn print_table(summary: &Summary, athlete: &Athlete, series: bool) {
+    let start_time = summary.start_time.parse().unwrap_or_default();
+    let rect = &summary.largest_rect;
+    println!("{:<16}{}", "start time", iso8601(start_time));
+    println!("{:<16}{} s", "total time", summary.total_time_secs);
+    println!("{:<16}{:.3} mi", "distance", summary.total_distance);
+    println!("{:<16}{:.1}", "calories", summary.total_calories);
+    println!(
+        "{:<16}{} ticks dropped, {:.1}% suspected bounces",
+        "debounce",
+        summary.debounce.dropped_ticks,
+        summary.debounce.bounce_rate * 100.
+    );
+    for discontinuity in &summary.discontinuities {
+        println!(
+            "{:<16}{} at {} s",
+            "device counter",
+            discontinuity.kind.name(),
+            discontinuity.time as f32 / 1000.
+        );
+    }
+    println!(
+        "{:<16}{:.2} mph from {} to {} s",
+        "max rectangle",
+        rect.height,
+        seconds(rect.start_time),
+        seconds(rect.end_time)
+    );
+    println!("records");
+    for name in record_names(athlete) {
+        match summary.distance_records.0.get(&name) {
+            Some(Some(record)) => println!(
+                "  {:<14}{} s ({} to {})",
+                name,
+                seconds(record.time),
+                seconds(record.start_time),
+                seconds(record.end_time)
+            ),
+            _ => println!("  {:<14}-", name),
+        }
+    }
+    if series {
+        println!(
+            "{:>8} {:>8} {:>8} {:>8} {:>8}",
+            "seconds", "mph", "raw mph", "miles", "kcal"
+        );
+        for d in &summary.interval_data {
+            println!(
+                "{:>8} {:>8.3} {:>8.3} {:>8.4} {:>8.4}",
+                seconds(d.time),
+                d.speed,
+                d.raw_speed,
+                d.distance,
+                d.calories
+            );
+        }
+    }
+}
+
+fn run(args: Args) -> Result<(), String> {
+    let bytes = read_input(&args.file)?;
+    let text = String::from_utf8_lossy(&bytes);
+    let mut options = SummaryOptions::default();
+    let mut weight = DEFAULT_WEIGHT;
+    // archives are json objects, gzipped unless written before versioning
+    let is_archive = bytes.starts_with(&[0x1f, 0x8b]) || text.trim_start().starts_with('{');
+    let raw_data = if is_archive {
+        let archive = Archive::decode("analyze", &bytes).map_err(|e| e.msg)?;
+        if let Some(treadmill) = &archive.treadmill {
+            options.ticks_per_mile = treadmill.ticks_per_mile;
+            options.debounce_time = treadmill.debounce_time;
+        }
+        if let Some(athlete) = &archive.athlete {
+            weight = athlete.weight;
+        }
+        archive.raw_data().map_err(|e| e.msg)?
+    } else {
+        RawData {
+            start_time: "0".to_string(),
+            tickstamps: parse_tick_list(&text)?,
+            clock_samples: vec![],
+            discontinuities: vec![],
+        }
+    };
+    options.ticks_per_mile = args.ticks_per_mile.unwrap_or(options.ticks_per_mile);
+    options.debounce_time = args.debounce_time.unwrap_or(options.debounce_time);
+    options.interval_size = args.interval_size.unwrap_or(options.interval_size);
+    options.speed_filter = args.speed_filter.unwrap_or(options.speed_filter);
+    options.adaptive_debounce &= !args.fixed_debounce;
+    if options.ticks_per_mile <= 0. || options.interval_size == 0 {
+        return Err("ticks per mile and interval must be positive".to_string());
+    }
+    let athlete = Athlete {
+        id: "analyze".to_string(),
+        name: "analyze".to_string(),
+        weight: args.weight.unwrap_or(weight),
+        units: Default::default(),
+        record_distances: default_record_distances(),
+    };
+
+    let summary = Summary::with_options("analyze", &athlete, raw_data, &options)
+        .map_err(|e| format!("run is not valid: {:?}", e))?;
+    match (args.json, args.series) {
+        (true, false) => println!("{}", json::to_string(&summary).map_err(|e| e.to_string())?),
+        (true, true) => println!(
+            "{}",
+            json!({ "summary": summary, "series": summary.interval_data })
+        ),
+        (false, series) => print_table(&summary, &athlete, series),
+    }
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let res = parse_args(&args).and_then(run);
+    if let Err(e) = res {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
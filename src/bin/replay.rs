@@ -0,0 +1,227 @@
+use dotenv::dotenv;
+use rocket::serde::json;
+use rusty_dusty::{
+    archive::Archive,
+    athlete::{default_record_distances, Athlete, DEFAULT_WEIGHT},
+    aws::{fetch_run, read_archive_from_s3},
+    config,
+    device::DeviceClient,
+    replay::{compare, compare_stored, replay_batches, Difference},
+    run::{Summary, Tickstamp},
+};
+use std::{env, fs, process, time::Duration};
+use tokio::time::{sleep_until, Instant};
+
+const USAGE: &str =
+    "usage: rusty-dusty-replay --device-key <key> --athlete <id> [options] (--run <id> | <file>)
+
+Replays the raw archive of a run, read from file or fetched from s3 by run id,
+through a running server: a new run is started as the given athlete, the archived
+ticks are posted in batches at their original pacing and the run is finished.
+The server's summary is then compared to the run's stored summary, when replaying
+by run id, and to the archive summarized anew, exiting with 2 if either differs.
+
+The replayed run is kept in the athlete's history and counts towards their
+personal records, so replay as a test athlete, never as the run's own athlete.
+
+options:
+  --server <url>        default http://localhost:8000
+  --speedup <x>         post x times faster than the original run, 1 by default
+  --batch <n>           ticks per post, 10 by default
+  --tolerance <x>       allowed difference of distances, speeds and calories, 0.001 by default";
+
+struct Args {
+    server: String,
+    device_key: String,
+    athlete_id: String,
+    run_id: Option<String>,
+    file: Option<String>,
+    speedup: f64,
+    batch_size: usize,
+    tolerance: f32,
+}
+
+fn parse_args(args: &[String]) -> Result<Args, String> {
+    let mut device_key = None;
+    let mut athlete_id = None;
+    let mut res = Args {
+        server: "http://localhost:8000".to_string(),
+        device_key: String::new(),
+        athlete_id: String::new(),
+        run_id: None,
+        file: None,
+        speedup: 1.,
+        batch_size: 10,
+        tolerance: 0.001,
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            match res.file {
+                None => res.file = Some(arg.to_string()),
+                Some(_) => return Err(USAGE.to_string()),
+            }
+            continue;
+        }
+        let value = args
+            .next()
+            .ok_or_else(|| format!("{} needs a value\n\n{}", arg, USAGE))?;
+        match arg.as_str() {
+            "--server" => res.server = value.to_string(),
+            "--device-key" => device_key = Some(value.to_string()),
+            "--athlete" => athlete_id = Some(value.to_string()),
+            "--run" => res.run_id = Some(value.to_string()),
+            "--speedup" => res.speedup = number(arg, value)?,
+            "--batch" => res.batch_size = number(arg, value)?,
+            "--tolerance" => res.tolerance = number(arg, value)?,
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+    res.device_key = device_key.ok_or(USAGE)?;
+    res.athlete_id = athlete_id.ok_or(USAGE)?;
+    if res.run_id.is_some() == res.file.is_some() {
+        return Err(USAGE.to_string());
+    }
+    if res.speedup <= 0. || res.batch_size == 0 {
+        return Err("--speedup and --batch must be positive".to_string());
+    }
+    Ok(res)
+}
+
+fn number<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value for {}: {:?}", name, value))
+}
+
+async fn read_archive(args: &Args) -> Result<Archive, String> {
+    match (&args.run_id, &args.file) {
+        (Some(run_id), _) => read_archive_from_s3(run_id).await.map_err(|e| e.msg),
+        (None, Some(path)) => {
+            let bytes = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+            Archive::decode(path, &bytes).map_err(|e| e.msg)
+        }
+        (None, None) => Err(USAGE.to_string()),
+    }
+}
+
+fn print_differences(against: &str, differences: &[Difference]) {
+    if differences.is_empty() {
+        println!("the replayed summary matches the {}", against);
+        return;
+    }
+    println!("differences from the {}:", against);
+    for d in differences {
+        println!("{:<28} {:>14} {:>14}", d.field, d.original, d.replayed);
+    }
+}
+
+// returns whether the replayed summary matches the stored and the recomputed one
+async fn run(args: Args) -> Result<bool, String> {
+    let archive = read_archive(&args).await?;
+    let athlete_id = args.athlete_id.clone();
+    // a run read from file may never have been stored
+    let stored = match &args.run_id {
+        Some(run_id) => Some(
+            fetch_run(run_id)
+                .await
+                .map_err(|e| e.msg)?
+                .ok_or_else(|| format!("no stored summary for run {}", run_id))?,
+        ),
+        None => None,
+    };
+
+    // the original as it was summarized at finalize, with the archived parameters
+    let mut options = config::get().analysis;
+    if let Some(treadmill) = &archive.treadmill {
+        options.ticks_per_mile = treadmill.ticks_per_mile;
+        options.debounce_time = treadmill.debounce_time;
+    }
+    let athlete = Athlete {
+        id: athlete_id.clone(),
+        name: "replay".to_string(),
+        weight: archive
+            .athlete
+            .as_ref()
+            .map_or(DEFAULT_WEIGHT, |a| a.weight),
+        units: Default::default(),
+        record_distances: default_record_distances(),
+    };
+    let raw_data = archive.raw_data().map_err(|e| e.msg)?;
+    let batches = replay_batches(&raw_data.tickstamps, args.batch_size);
+    let first_tick = raw_data.tickstamps.first().copied().unwrap_or_default();
+    let original = Summary::with_options(&archive.run_id, &athlete, raw_data, &options)
+        .map_err(|e| format!("archived run is not valid: {:?}", e))?;
+
+    // sped up posts arrive too early for the device clock to line up with ours
+    let device_time = |time: Tickstamp| (args.speedup == 1.).then_some(time);
+    let device = DeviceClient::new(&args.server, &args.device_key);
+    let res = device
+        .new_run(&athlete_id, device_time(first_tick))
+        .await
+        .map_err(|e| format!("error starting run: {}", e))?;
+    let run_id = match res.status.is_success() {
+        true => res.body,
+        false => return Err(format!("error starting run: {} {}", res.status, res.body)),
+    };
+    println!(
+        "replaying {} as {}: {} ticks in {} posts",
+        archive.run_id,
+        run_id,
+        batches.iter().map(|b| b.ticks.len()).sum::<usize>(),
+        batches.len()
+    );
+
+    let start = Instant::now();
+    for batch in &batches {
+        let send_at = Duration::from_secs_f64(batch.send_at as f64 / 1000. / args.speedup);
+        sleep_until(start + send_at).await;
+        let res = device
+            .post_ticks(&run_id, &batch.ticks, device_time(batch.device_time))
+            .await
+            .map_err(|e| format!("error posting ticks: {}", e))?;
+        if !res.status.is_success() {
+            return Err(format!("error posting ticks: {} {}", res.status, res.body));
+        }
+    }
+
+    let res = device
+        .finish(&run_id)
+        .await
+        .map_err(|e| format!("error finishing run: {}", e))?;
+    let body = match res.status.is_success() {
+        true => res.body,
+        false => return Err(format!("error finishing run: {} {}", res.status, res.body)),
+    };
+    let replayed: Summary =
+        json::from_str(&body).map_err(|e| format!("error reading summary: {}", e))?;
+
+    let mut matches = true;
+    if let Some(stored) = &stored {
+        let differences = compare_stored(stored, &replayed, args.tolerance);
+        print_differences("stored summary", &differences);
+        matches &= differences.is_empty();
+    }
+    let differences = compare(&original, &replayed, args.tolerance);
+    print_differences("archive summarized anew", &differences);
+    Ok(matches && differences.is_empty())
+}
+
+#[tokio::main]
+async fn main() {
+    dotenv().ok();
+    let args: Vec<String> = env::args().skip(1).collect();
+    let res = match (parse_args(&args), config::init()) {
+        (Ok(args), Ok(_)) => run(args).await,
+        (Err(e), _) => Err(e),
+        (_, Err(e)) => Err(e.msg),
+    };
+    match res {
+        Ok(true) => (),
+        Ok(false) => process::exit(2),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,249 @@
+use rusty_dusty::{
+    device::{DeviceClient, Response},
+    run::Tickstamp,
+    simulate::{simulate, LatencyStats, SimulationOptions, Workout},
+};
+use std::{
+    collections::BTreeMap,
+    env,
+    future::Future,
+    process,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::time::sleep_until;
+
+const USAGE: &str = "usage: rusty-dusty-simulate --device-key <key> --athlete <id> [options]
+
+Drives simulated treadmills against a running server: each run is started with
+new-run, posts its ticks in batches as a device would and is then finished.
+
+options:
+  --server <url>        default http://localhost:8000
+  --runs <n>            concurrent runs, 1 by default
+  --workout <workout>   steady, intervals (default), short or \"<seconds>@<mph>,...\"
+  --speedup <x>         post x times faster than real time, 1 by default
+  --batch <ms>          time between posts, 1000 by default
+  --bounce <p>          chance of a switch bounce per tick, 0.01 by default
+  --drift <ppm>         device clock drift, 50 by default
+  --drop <p>            chance of a batch being lost, 0 by default
+  --seed <n>            seed of the first run, later runs count up from it";
+
+struct Args {
+    server: String,
+    device_key: String,
+    athlete_id: String,
+    runs: u64,
+    workout: Workout,
+    speedup: f64,
+    options: SimulationOptions,
+}
+
+fn parse_args(args: &[String]) -> Result<Args, String> {
+    let mut device_key = None;
+    let mut athlete_id = None;
+    let mut res = Args {
+        server: "http://localhost:8000".to_string(),
+        device_key: String::new(),
+        athlete_id: String::new(),
+        runs: 1,
+        workout: Workout::preset("intervals").expect("presets are valid"),
+        speedup: 1.,
+        options: SimulationOptions::default(),
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let value = args
+            .next()
+            .ok_or_else(|| format!("{} needs a value\n\n{}", arg, USAGE))?;
+        match arg.as_str() {
+            "--server" => res.server = value.to_string(),
+            "--device-key" => device_key = Some(value.to_string()),
+            "--athlete" => athlete_id = Some(value.to_string()),
+            "--runs" => res.runs = number(arg, value)?,
+            "--workout" => {
+                res.workout = Workout::preset(value)
+                    .or_else(|| Workout::parse(value))
+                    .ok_or_else(|| format!("invalid workout: {:?}", value))?
+            }
+            "--speedup" => res.speedup = number(arg, value)?,
+            "--batch" => res.options.batch_interval = number(arg, value)?,
+            "--bounce" => res.options.bounce = number(arg, value)?,
+            "--drift" => res.options.drift_ppm = number(arg, value)?,
+            "--drop" => res.options.drop_batch = number(arg, value)?,
+            "--seed" => res.options.seed = number(arg, value)?,
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+    res.device_key = device_key.ok_or(USAGE)?;
+    res.athlete_id = athlete_id.ok_or(USAGE)?;
+    if res.speedup <= 0. {
+        return Err("--speedup must be positive".to_string());
+    }
+    Ok(res)
+}
+
+fn number<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value for {}: {:?}", name, value))
+}
+
+// one request as seen by the simulated device
+struct Sample {
+    endpoint: &'static str,
+    latency: Duration,
+    outcome: Result<reqwest::StatusCode, String>,
+}
+
+impl Sample {
+    fn is_error(&self) -> bool {
+        !matches!(self.outcome, Ok(status) if status.is_success())
+    }
+}
+
+async fn timed(
+    endpoint: &'static str,
+    req: impl Future<Output = Result<Response, reqwest::Error>>,
+    samples: &mut Vec<Sample>,
+) -> Option<String> {
+    let start = Instant::now();
+    let res = req.await;
+    let latency = start.elapsed();
+    match res {
+        Ok(res) => {
+            samples.push(Sample {
+                endpoint,
+                latency,
+                outcome: Ok(res.status),
+            });
+            res.success()
+        }
+        Err(e) => {
+            samples.push(Sample {
+                endpoint,
+                latency,
+                outcome: Err(e.to_string()),
+            });
+            None
+        }
+    }
+}
+
+async fn drive_run(device: DeviceClient, args: Arc<Args>, seed: u64) -> (bool, Vec<Sample>) {
+    let options = SimulationOptions {
+        seed,
+        ..args.options
+    };
+    let simulated = simulate(&args.workout, &options);
+    let mut samples = vec![];
+    // sped up posts arrive too early for the device clock to line up with ours
+    let device_time = |time: Tickstamp| (args.speedup == 1.).then_some(time);
+
+    let new_run = device.new_run(&args.athlete_id, device_time(simulated.device_start));
+    let run_id = match timed("new-run", new_run, &mut samples).await {
+        Some(id) => id,
+        None => return (false, samples),
+    };
+
+    let start = tokio::time::Instant::now();
+    for batch in &simulated.batches {
+        let send_at = Duration::from_secs_f64(batch.send_at as f64 / 1000. / args.speedup);
+        sleep_until(start + send_at).await;
+        timed(
+            "post-data",
+            device.post_ticks(&run_id, &batch.ticks, device_time(batch.device_time)),
+            &mut samples,
+        )
+        .await;
+    }
+
+    let finished = timed("finish", device.finish(&run_id), &mut samples)
+        .await
+        .is_some();
+    (finished, samples)
+}
+
+fn millis(d: Duration) -> f64 {
+    d.as_secs_f64() * 1000.
+}
+
+fn report(samples: Vec<Sample>, finished: u64, runs: u64, elapsed: Duration) {
+    println!(
+        "{} of {} runs finished in {:.1} s, {} requests ({:.1}/s)",
+        finished,
+        runs,
+        elapsed.as_secs_f64(),
+        samples.len(),
+        samples.len() as f64 / elapsed.as_secs_f64()
+    );
+    println!(
+        "{:<10} {:>8} {:>7} {:>9} {:>9} {:>9} {:>9}",
+        "endpoint", "requests", "errors", "p50 ms", "p95 ms", "p99 ms", "max ms"
+    );
+    let mut errors: BTreeMap<String, usize> = BTreeMap::new();
+    for endpoint in ["new-run", "post-data", "finish"] {
+        let of_endpoint: Vec<&Sample> = samples.iter().filter(|s| s.endpoint == endpoint).collect();
+        let error_count = of_endpoint.iter().filter(|s| s.is_error()).count();
+        for s in of_endpoint.iter().filter(|s| s.is_error()) {
+            let error = match &s.outcome {
+                Ok(status) => status.to_string(),
+                Err(e) => e.clone(),
+            };
+            *errors
+                .entry(format!("{}: {}", endpoint, error))
+                .or_default() += 1;
+        }
+        if let Some(stats) = LatencyStats::new(of_endpoint.iter().map(|s| s.latency).collect()) {
+            println!(
+                "{:<10} {:>8} {:>7} {:>9.1} {:>9.1} {:>9.1} {:>9.1}",
+                endpoint,
+                stats.count,
+                error_count,
+                millis(stats.p50),
+                millis(stats.p95),
+                millis(stats.p99),
+                millis(stats.max)
+            );
+        }
+    }
+    for (error, count) in errors {
+        println!("{:>6} x {}", count, error);
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args = match parse_args(&args) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+    let args = Arc::new(args);
+    let device = DeviceClient::new(&args.server, &args.device_key);
+    let start = Instant::now();
+    let handles: Vec<_> = (0..args.runs)
+        .map(|i| {
+            tokio::spawn(drive_run(
+                device.clone(),
+                args.clone(),
+                args.options.seed + i,
+            ))
+        })
+        .collect();
+
+    let mut finished = 0;
+    let mut samples = vec![];
+    for handle in handles {
+        let (ok, run_samples) = handle.await.expect("simulated run panicked");
+        finished += ok as u64;
+        samples.extend(run_samples);
+    }
+    report(samples, finished, args.runs, start.elapsed());
+    if finished < args.runs {
+        process::exit(1);
+    }
+}
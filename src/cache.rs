@@ -1,16 +1,15 @@
-use std::env;
-
 use redis::Commands;
 
+use crate::config;
+
 #[derive(Debug)]
 pub struct CacheError {
     pub msg: String,
 }
 
 fn redis_connection() -> Result<redis::Connection, CacheError> {
-    let redis_host = env::var("REDIS_HOST").unwrap_or("127.0.0.1".to_string());
-    let redis_port = env::var("REDIS_PORT").unwrap_or("6379".to_string());
-    let redis_path = format!("redis://{}:{}", redis_host, redis_port);
+    let redis = &config::get().redis;
+    let redis_path = format!("redis://{}:{}", redis.host, redis.port);
     match redis::Client::open(redis_path) {
         Ok(client) => match client.get_connection() {
             Ok(conn) => Ok(conn),
@@ -30,7 +29,7 @@ pub fn flushdb() -> Result<String, CacheError> {
 }
 
 
-pub fn get(key: String) -> Result<String, CacheError> {
+pub fn get(key: String) -> Result<Option<String>, CacheError> {
     let mut connection = redis_connection()?;
     match connection.get(key) {
         Ok(res) => Ok(res),
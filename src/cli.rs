@@ -0,0 +1,92 @@
+use rocket::serde::json;
+use std::{fs, path::Path};
+
+use crate::{
+    aws::{fetch_athlete, read_archive_from_s3},
+    import::{self, DistanceSeries, ImportFormat},
+    resummarize,
+};
+
+const USAGE: &str = "usage: rusty-dusty resummarize (<run-id>... | --all)";
+const IMPORT_USAGE: &str =
+    "usage: rusty-dusty import <athlete-id> [--start-time <epoch-millis>] <file.tcx|gpx|csv>...";
+const GOLDEN_USAGE: &str = "usage: rusty-dusty golden <run-id> <name>";
+
+// rusty-dusty resummarize <run-id>...   recompute the given runs
+// rusty-dusty resummarize --all         recompute every run and rebuild personal records
+pub async fn resummarize(args: &[String]) -> Result<(), String> {
+    if args.is_empty() {
+        return Err(USAGE.to_string());
+    }
+    if args.iter().any(|a| a == "--all") {
+        let report = resummarize::all().await.map_err(|e| e.message)?;
+        println!("{}", json::to_string(&report).map_err(|e| e.to_string())?);
+        return Ok(());
+    }
+    for run_id in args {
+        let summary = resummarize::one(run_id)
+            .await
+            .map_err(|e| format!("{}: {}", run_id, e.message))?;
+        println!("{}", json::to_string(&summary).map_err(|e| e.to_string())?);
+    }
+    Ok(())
+}
+
+// rusty-dusty import <athlete-id> <file>...   store each file as a run of the athlete,
+// the format is taken from the extension; csv files need --start-time
+pub async fn import(args: &[String]) -> Result<(), String> {
+    let (athlete_id, mut rest) = match args.split_first() {
+        Some((athlete_id, rest)) if !rest.is_empty() => (athlete_id, rest),
+        _ => return Err(IMPORT_USAGE.to_string()),
+    };
+    let mut start_time = None;
+    if rest[0] == "--start-time" {
+        let value = rest.get(1).ok_or(IMPORT_USAGE)?;
+        start_time = Some(value.parse::<u64>().map_err(|_| IMPORT_USAGE)?);
+        rest = &rest[2..];
+    }
+    if rest.is_empty() {
+        return Err(IMPORT_USAGE.to_string());
+    }
+    let athlete = fetch_athlete(athlete_id)
+        .await
+        .map_err(|e| e.msg)?
+        .ok_or_else(|| format!("no athlete with id {}", athlete_id))?;
+
+    for file in rest {
+        let format = Path::new(file)
+            .extension()
+            .and_then(|e| ImportFormat::from_name(&e.to_string_lossy()))
+            .ok_or_else(|| format!("{}: unknown import format", file))?;
+        let text = fs::read_to_string(file).map_err(|e| format!("{}: {}", file, e))?;
+        let series = DistanceSeries::parse(format, &text, start_time)
+            .map_err(|e| format!("{}: {}", file, e.msg))?;
+        let run_id = import::run_id(&athlete.id, series.start_time);
+        let summary = import::store(&run_id, &athlete, format, &series)
+            .await
+            .map_err(|e| format!("{}: {}", file, e.message))?;
+        println!("{}", json::to_string(&summary).map_err(|e| e.to_string())?);
+    }
+    Ok(())
+}
+
+// rusty-dusty golden <run-id> <name>   save the run's archive, anonymized, as
+// fixtures/golden/<name>.archive; bless it to write the expected summary
+pub async fn golden(args: &[String]) -> Result<(), String> {
+    let (run_id, name) = match args {
+        [run_id, name] => (run_id, name),
+        _ => return Err(GOLDEN_USAGE.to_string()),
+    };
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("fixtures/golden")
+        .join(format!("{}.archive", name));
+    if path.exists() {
+        return Err(format!("{} exists already", path.display()));
+    }
+    let mut archive = read_archive_from_s3(run_id).await.map_err(|e| e.msg)?;
+    archive.anonymize(name);
+    let data = archive.encode().map_err(|e| e.msg)?;
+    fs::write(&path, data).map_err(|e| format!("{}: {}", path.display(), e))?;
+    println!("{}", path.display());
+    Ok(())
+}
@@ -0,0 +1,194 @@
+// relates the device's millis counter to wall-clock time: devices may send their
+// counter along with new-run and tick posts, and the server notes when it got it
+use serde::{Deserialize, Serialize};
+
+use crate::run::{SummaryOptions, Tickstamp, Timestamp};
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub struct ClockSample {
+    #[serde(rename = "deviceTime")]
+    pub device_time: Tickstamp,
+    #[serde(rename = "wallTime")]
+    pub wall_time: u64, // epoch millis when the server received it
+}
+
+impl ClockSample {
+    // cached as members of a sorted set, scored by device time on the run's
+    // stitched timeline, which decoding moves back by base, see timeline.rs
+    pub fn encode(device_time: u64, wall_time: u64) -> String {
+        format!("{}:{}", device_time, wall_time)
+    }
+
+    pub fn decode(member: &str, base: u64) -> Option<ClockSample> {
+        let (device_time, wall_time) = member.split_once(':')?;
+        let device_time: u64 = device_time.parse().ok()?;
+        Some(ClockSample {
+            device_time: Tickstamp::try_from(device_time.checked_sub(base)?).ok()?,
+            wall_time: wall_time.parse().ok()?,
+        })
+    }
+}
+
+// the sample whose wall time is earliest for its device time, at rate wall
+// millis per device milli
+fn least_delayed<'a>(
+    points: impl Iterator<Item = &'a (f64, f64)>,
+    rate: f64,
+) -> Option<(f64, f64)> {
+    points
+        .copied()
+        .min_by(|(d1, w1), (d2, w2)| (w1 - d1 * rate).total_cmp(&(w2 - d2 * rate)))
+}
+
+// which sample is least delayed depends on the rate, so the two are refined
+// together until the same samples are picked again
+const FIT_ROUNDS: usize = 8;
+
+// wall-clock time of a run's timeline, whose time 0 is the first tick
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub struct ClockSync {
+    #[serde(rename = "start")]
+    pub start: u64, // epoch millis of the first tick
+    #[serde(rename = "driftPpm")]
+    pub drift_ppm: f64, // how much faster (or, negative, slower) the device clock runs
+    #[serde(rename = "samples")]
+    pub samples: usize,
+}
+
+impl ClockSync {
+    // network delay only ever makes samples arrive late, so the least delayed
+    // sample of each half of the run is trusted and drift is the slope between
+    // the two; runs too short to tell drift from delay are taken to have none
+    pub fn estimate(
+        samples: &[ClockSample],
+        first_tick: Tickstamp,
+        options: &SummaryOptions,
+    ) -> Option<ClockSync> {
+        // relative to the first tick and first sample, so that f64s stay exact
+        let wall_base = samples.first()?.wall_time;
+        let points: Vec<(f64, f64)> = samples
+            .iter()
+            .map(|s| {
+                (
+                    s.device_time as f64 - first_tick as f64,
+                    s.wall_time as f64 - wall_base as f64,
+                )
+            })
+            .collect();
+        let first = points.iter().map(|(d, _)| *d).fold(f64::INFINITY, f64::min);
+        let last = points
+            .iter()
+            .map(|(d, _)| *d)
+            .fold(f64::NEG_INFINITY, f64::max);
+        let middle = (first + last) / 2.;
+
+        let mut rate = 1.; // wall millis per device milli
+        if last - first >= options.clock_min_span as f64 {
+            let mut fitted = 1.;
+            let mut picked = None;
+            for _ in 0..FIT_ROUNDS {
+                let early = least_delayed(points.iter().filter(|(d, _)| *d < middle), fitted);
+                let late = least_delayed(points.iter().filter(|(d, _)| *d >= middle), fitted);
+                let (Some((d1, w1)), Some((d2, w2))) = (early, late) else {
+                    break;
+                };
+                if picked == Some((d1, d2)) {
+                    break;
+                }
+                picked = Some((d1, d2));
+                fitted = (w2 - w1) / (d2 - d1);
+            }
+            // anything more is a glitch, like a device that rebooted mid-run
+            if ((1. / fitted - 1.) * 1_000_000.).abs() <= options.clock_max_drift_ppm as f64 {
+                rate = fitted;
+            }
+        }
+        let start = points
+            .iter()
+            .map(|(d, w)| w - d * rate)
+            .fold(f64::INFINITY, f64::min);
+        Some(ClockSync {
+            start: (wall_base as f64 + start).round() as u64,
+            drift_ppm: (1. / rate - 1.) * 1_000_000.,
+            samples: samples.len(),
+        })
+    }
+
+    // epoch millis at time into the run
+    pub fn wall_time(&self, time: Timestamp) -> u64 {
+        self.start + (time as f64 / (1. + self.drift_ppm / 1_000_000.)).round() as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOOT: Tickstamp = 5_000_000;
+    const START: u64 = 1656202584971;
+
+    // a device whose clock runs drift_ppm fast, sampled every minute for an hour
+    // from a minute before the first tick, with delays cycling up to 90 ms
+    fn samples(drift_ppm: f64) -> Vec<ClockSample> {
+        (0..62)
+            .map(|i| {
+                let real = i as f64 * 60_000. - 60_000.;
+                ClockSample {
+                    device_time: (BOOT as f64 + real * (1. + drift_ppm / 1_000_000.)) as Tickstamp,
+                    wall_time: (START as f64 + real) as u64 + [40, 90, 3, 25][i % 4],
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn samples_round_trip() {
+        let sample = ClockSample {
+            device_time: 123,
+            wall_time: START,
+        };
+        let member = ClockSample::encode(123, START);
+        assert_eq!(ClockSample::decode(&member, 0), Some(sample));
+        let member = ClockSample::encode(Tickstamp::MAX as u64 + 123, START);
+        assert_eq!(ClockSample::decode(&member, 0), None);
+        assert_eq!(
+            ClockSample::decode(&member, Tickstamp::MAX as u64),
+            Some(ClockSample {
+                device_time: 123,
+                ..sample
+            })
+        );
+        assert_eq!(ClockSample::decode("123", 0), None);
+        assert_eq!(ClockSample::decode("x:1", 0), None);
+    }
+
+    #[test]
+    fn estimate_drift() {
+        let options = SummaryOptions::default();
+        assert_eq!(ClockSync::estimate(&[], BOOT, &options), None);
+        for drift_ppm in [0., 80., -150.] {
+            let sync = ClockSync::estimate(&samples(drift_ppm), BOOT, &options).unwrap();
+            assert_eq!(sync.samples, 62);
+            assert!((sync.drift_ppm - drift_ppm).abs() < 2., "{:?}", sync);
+            // within the least delay, 3 ms, of the real time
+            let end = (3_600_000. * (1. + drift_ppm / 1_000_000.)) as Timestamp;
+            assert!(sync.wall_time(0).abs_diff(START) <= 4, "{:?}", sync);
+            assert!(
+                sync.wall_time(end).abs_diff(START + 3_600_000) <= 4,
+                "{:?}",
+                sync
+            );
+        }
+    }
+
+    #[test]
+    fn estimate_without_drift() {
+        let options = SummaryOptions::default();
+        // a single handshake only gives the offset
+        let sync = ClockSync::estimate(&samples(80.)[1..2], BOOT, &options).unwrap();
+        assert_eq!((sync.start, sync.drift_ppm), (START + 90, 0.));
+        // as does a clock that seems to run 2% fast
+        let sync = ClockSync::estimate(&samples(20_000.), BOOT, &options).unwrap();
+        assert_eq!(sync.drift_ppm, 0.);
+    }
+}
@@ -0,0 +1,295 @@
+// everything that may differ between deployments, read once at startup from
+// Rocket.toml and the environment; tuning defaults live in constants.rs
+use rocket::figment::{providers::Env, Figment};
+use serde::Deserialize;
+use std::sync::OnceLock;
+
+use crate::{
+    constants::{ARCHIVE_SHORT_RUNS, MAX_INTERVAL_SIZE, MAX_TICK_REWIND, MIN_INTERVAL_SIZE},
+    run::SummaryOptions,
+};
+
+// the variables deployments set before the configuration file existed, and the
+// keys they stand for; they take precedence over Rocket.toml and ROCKET_ ones
+const LEGACY_ENV: [(&str, &str); 8] = [
+    ("AWS_S3_RAW_DATA_BUCKET", "aws.raw_data_bucket"),
+    ("AWS_DYNAMO_TABLE_SUMMARY", "aws.summary_table"),
+    ("AWS_DYNAMO_TABLE_RECORDS", "aws.records_table"),
+    ("AWS_DYNAMO_TABLE_ATHLETES", "aws.athletes_table"),
+    ("AWS_DYNAMO_TABLE_API_KEYS", "aws.api_keys_table"),
+    ("REDIS_HOST", "redis.host"),
+    ("REDIS_PORT", "redis.port"),
+    ("ADMIN_API_KEY_HASH", "admin_api_key_hash"),
+];
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+#[derive(Debug)]
+pub struct ConfigError {
+    pub msg: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    #[serde(default)]
+    pub analysis: SummaryOptions,
+    #[serde(default)]
+    pub runs: RunConfig,
+    pub aws: AwsConfig,
+    #[serde(default)]
+    pub redis: RedisConfig,
+    #[serde(default)]
+    pub admin_api_key_hash: Option<String>, // admin keys are refused without one
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RunConfig {
+    pub min_interval_size: u32, // in millis, the finest series a request may ask for
+    pub max_interval_size: u32, // in millis, the coarsest series a request may ask for
+    pub archive_short_runs: bool, // keep raw ticks of rejected runs in s3
+    pub max_tick_rewind: u32,   // in millis, readings further behind are a restarted counter
+}
+
+impl Default for RunConfig {
+    fn default() -> RunConfig {
+        RunConfig {
+            min_interval_size: MIN_INTERVAL_SIZE,
+            max_interval_size: MAX_INTERVAL_SIZE,
+            archive_short_runs: ARCHIVE_SHORT_RUNS,
+            max_tick_rewind: MAX_TICK_REWIND,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AwsConfig {
+    pub raw_data_bucket: String,
+    pub summary_table: String,
+    pub records_table: String,
+    pub athletes_table: String,
+    pub api_keys_table: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RedisConfig {
+    pub host: String,
+    pub port: u16,
+}
+
+impl Default for RedisConfig {
+    fn default() -> RedisConfig {
+        RedisConfig {
+            host: "127.0.0.1".to_string(),
+            port: 6379,
+        }
+    }
+}
+
+// Rocket's own sources, Rocket.toml and ROCKET_ variables, then the legacy ones
+pub fn figment() -> Figment {
+    let legacy = Env::raw()
+        .filter(|key| LEGACY_ENV.iter().any(|(name, _)| key == *name))
+        .map(|key| {
+            let (_, path) = LEGACY_ENV
+                .iter()
+                .find(|(name, _)| key == *name)
+                .expect("only legacy keys pass the filter");
+            (*path).into()
+        });
+    rocket::Config::figment().merge(legacy)
+}
+
+pub fn load(figment: &Figment) -> Result<Config, ConfigError> {
+    let config: Config = figment.extract().map_err(|e| ConfigError {
+        msg: format!("invalid configuration: {}", e),
+    })?;
+    config.validate()?;
+    Ok(config)
+}
+
+// loads and checks the configuration unless that already happened; servers and
+// tools call this before anything that reads it
+pub fn init() -> Result<&'static Config, ConfigError> {
+    if let Some(config) = CONFIG.get() {
+        return Ok(config);
+    }
+    let config = load(&figment())?;
+    Ok(CONFIG.get_or_init(|| config))
+}
+
+// the configuration loaded by init
+pub fn get() -> &'static Config {
+    CONFIG
+        .get()
+        .expect("the configuration is loaded at startup")
+}
+
+impl Config {
+    // every problem at once, so a broken deployment is fixed in one go
+    fn validate(&self) -> Result<(), ConfigError> {
+        let mut problems = vec![];
+        let analysis = &self.analysis;
+        if analysis.ticks_per_mile.is_nan() || analysis.ticks_per_mile <= 0. {
+            problems.push(format!(
+                "analysis.ticks_per_mile must be positive, got {}",
+                analysis.ticks_per_mile
+            ));
+        }
+        if !(0. ..1.).contains(&analysis.debounce_period_ratio) {
+            problems.push(format!(
+                "analysis.debounce_period_ratio must be at least 0 and below 1, got {}",
+                analysis.debounce_period_ratio
+            ));
+        }
+        if analysis.debounce_window == 0 {
+            problems.push("analysis.debounce_window must be at least 1".to_string());
+        }
+        if let Err(e) = analysis.speed_filter.validate() {
+            problems.push(format!("analysis.speed_filter: {}", e));
+        }
+        if analysis.min_run_distance.is_nan() || analysis.min_run_distance < 0. {
+            problems.push(format!(
+                "analysis.min_run_distance must not be negative, got {}",
+                analysis.min_run_distance
+            ));
+        }
+        if analysis.clock_max_drift_ppm.is_nan() || analysis.clock_max_drift_ppm < 0. {
+            problems.push(format!(
+                "analysis.clock_max_drift_ppm must not be negative, got {}",
+                analysis.clock_max_drift_ppm
+            ));
+        }
+        let runs = &self.runs;
+        if runs.min_interval_size == 0 || runs.min_interval_size > runs.max_interval_size {
+            problems.push(format!(
+                "runs.min_interval_size must be positive and at most runs.max_interval_size, \
+                 got {} and {}",
+                runs.min_interval_size, runs.max_interval_size
+            ));
+        }
+        if !(runs.min_interval_size..=runs.max_interval_size).contains(&analysis.interval_size) {
+            problems.push(format!(
+                "analysis.interval_size must be between runs.min_interval_size and \
+                 runs.max_interval_size, got {}",
+                analysis.interval_size
+            ));
+        }
+        if runs.max_tick_rewind == 0 {
+            problems.push("runs.max_tick_rewind must be at least 1".to_string());
+        }
+        for (key, value) in [
+            ("aws.raw_data_bucket", &self.aws.raw_data_bucket),
+            ("aws.summary_table", &self.aws.summary_table),
+            ("aws.records_table", &self.aws.records_table),
+            ("aws.athletes_table", &self.aws.athletes_table),
+            ("aws.api_keys_table", &self.aws.api_keys_table),
+        ] {
+            if value.trim().is_empty() {
+                problems.push(format!("{} must not be empty", key));
+            }
+        }
+        match problems.is_empty() {
+            true => Ok(()),
+            false => Err(ConfigError {
+                msg: format!("invalid configuration: {}", problems.join("; ")),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::SpeedFilterConfig;
+    use rocket::figment::providers::{Format, Toml};
+
+    const AWS: &str = r#"
+        [aws]
+        raw_data_bucket = "raw"
+        summary_table = "summaries"
+        records_table = "records"
+        athletes_table = "athletes"
+        api_keys_table = "keys"
+    "#;
+
+    fn load_toml(toml: &str) -> Result<Config, ConfigError> {
+        load(&Figment::from(Toml::string(toml)))
+    }
+
+    #[test]
+    fn defaults_fill_in() {
+        let config = load_toml(AWS).unwrap();
+        assert_eq!(config.analysis, SummaryOptions::default());
+        assert_eq!(config.runs.max_interval_size, MAX_INTERVAL_SIZE);
+        assert_eq!(config.redis.port, 6379);
+        assert_eq!(config.aws.summary_table, "summaries");
+        assert_eq!(config.admin_api_key_hash, None);
+    }
+
+    #[test]
+    fn analysis_overrides() {
+        let toml = format!(
+            r#"{}
+            [analysis]
+            interval_size = 250
+            speed_filter = {{ name = "median", window = 7 }}
+            "#,
+            AWS
+        );
+        let config = load_toml(&toml).unwrap();
+        assert_eq!(config.analysis.interval_size, 250);
+        assert_eq!(
+            config.analysis.speed_filter,
+            SpeedFilterConfig::Median { window: 7 }
+        );
+        assert_eq!(
+            config.analysis.debounce_time,
+            SummaryOptions::default().debounce_time
+        );
+
+        let toml = format!(
+            "{}\n[analysis]\nspeed_filter = {{ name = \"kalman\" }}",
+            AWS
+        );
+        assert_eq!(
+            load_toml(&toml).unwrap().analysis.speed_filter,
+            SpeedFilterConfig::from_name("kalman").unwrap()
+        );
+    }
+
+    #[test]
+    fn missing_settings_are_named() {
+        let e = load_toml("[aws]\nsummary_table = \"summaries\"").unwrap_err();
+        assert!(e.msg.contains("raw_data_bucket"), "{}", e.msg);
+        let e = load_toml(&AWS.replace("\"raw\"", "\"\"")).unwrap_err();
+        assert!(
+            e.msg.contains("aws.raw_data_bucket must not be empty"),
+            "{}",
+            e.msg
+        );
+    }
+
+    #[test]
+    fn invalid_tuning_is_reported_at_once() {
+        let toml = format!(
+            r#"{}
+            [analysis]
+            ticks_per_mile = 0
+            interval_size = 50
+            speed_filter = {{ name = "ema", smoothing = 1.5 }}
+            "#,
+            AWS
+        );
+        let e = load_toml(&toml).unwrap_err();
+        assert!(e.msg.contains("ticks_per_mile"), "{}", e.msg);
+        assert!(e.msg.contains("interval_size"), "{}", e.msg);
+        assert!(e.msg.contains("smoothing"), "{}", e.msg);
+        let toml = format!(
+            "{}\n[analysis]\nspeed_filter = {{ name = \"gaussian\" }}",
+            AWS
+        );
+        assert!(load_toml(&toml).is_err());
+    }
+}
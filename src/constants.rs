@@ -2,8 +2,29 @@
 pub const TICKS_PER_MILE: f32 = 5280.0 * (6.0 / 3.12);
 pub const MILLIS_PER_HOUR: u32 = 60 * 60 * 1000;
 pub const KILOMETERS_PER_MILE: f32 = 1.60934;
+pub const METERS_PER_MILE: f32 = 1609.344;
+pub const MPH_TO_METERS_PER_SECOND: f32 = 0.44704;
 
-// configuration constants
-pub const DEBOUNCE_TIME: u32 = 20; // in millis
-pub const SPEED_SMOOTHING: f32 = 0.5;
+// defaults of the runtime configuration, see config.rs
+pub const DEBOUNCE_TIME: u32 = 20; // in millis, the least time between two ticks
+pub const ADAPTIVE_DEBOUNCE: bool = true; // also drop ticks much sooner than the current pace
+pub const DEBOUNCE_PERIOD_RATIO: f32 = 0.5; // of the tick period, below which ticks are bounces
+pub const DEBOUNCE_WINDOW: usize = 8; // ticks over which the tick period is estimated
+pub const DEBOUNCE_MAX_PERIOD: u32 = 1000; // in millis, longer gaps are pauses, not pace
+pub const SPEED_FILTER: &str = "ema"; // ema, moving-average, median or kalman
+pub const SPEED_SMOOTHING: f32 = 0.5; // for ema
+pub const SPEED_FILTER_WINDOW: usize = 5; // in intervals, for moving-average and median
+pub const KALMAN_PROCESS_NOISE: f32 = 0.5; // in mph², for kalman
+pub const KALMAN_MEASUREMENT_NOISE: f32 = 1.0; // in mph², for kalman
 pub const INTERVAL_SIZE: u32 = 1000; // resolution of data in ms
+pub const MIN_INTERVAL_SIZE: u32 = 100; // in ms, the finest resolution a request may ask for
+pub const MAX_INTERVAL_SIZE: u32 = 60_000; // in ms, the coarsest resolution a request may ask for
+pub const CLOCK_MIN_SPAN: u32 = 300_000; // in millis of clock samples, below which drift isn't estimated
+pub const CLOCK_MAX_DRIFT_PPM: f32 = 500.; // larger estimates are glitches and ignored
+pub const MIN_RUN_TIME: u32 = 10; // in seconds, shorter runs are rejected at finalize
+pub const MIN_RUN_DISTANCE: f32 = 0.01; // in miles, shorter runs are rejected at finalize
+pub const ARCHIVE_SHORT_RUNS: bool = true; // keep raw ticks of rejected runs in s3
+pub const MAX_TICK_REWIND: u32 = 60_000; // in millis, readings further behind are a restarted counter
+pub const MAX_IMPORT_DISTANCE: f32 = 100.; // in miles, longer imported runs are rejected
+pub const MAX_IMPORT_DURATION: u64 = 86_400_000; // in millis, longer imported runs are rejected
+pub const MAX_IMPORT_TICKS: u32 = 2_000_000; // the most ticks an imported run is turned into
@@ -0,0 +1,197 @@
+use crate::{
+    athlete::{Athlete, Units},
+    export::iso8601,
+    run::{IntervalDatum, RunListing, Timestamp},
+};
+
+// quotes a field when it holds a separator, a quote or a line break
+fn field(value: &str) -> String {
+    match value.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", value.replace('"', "\"\"")),
+        false => value.to_string(),
+    }
+}
+
+fn line(fields: Vec<String>) -> String {
+    let mut line = fields.join(",");
+    line.push('\n');
+    line
+}
+
+// record columns follow the athlete's record distances, shortest first
+pub fn record_names(athlete: &Athlete) -> Vec<String> {
+    let mut distances: Vec<(&String, &f32)> = athlete.record_distances.iter().collect();
+    distances.sort_by(|a, b| a.1.total_cmp(b.1).then(a.0.cmp(b.0)));
+    distances
+        .into_iter()
+        .map(|(name, _)| name.clone())
+        .collect()
+}
+
+pub fn run_header(units: Units, record_names: &[String]) -> String {
+    let mut fields = vec![
+        "runId".to_string(),
+        "startTime".to_string(),
+        "totalTimeSeconds".to_string(),
+        format!("totalDistance{}", units.distance_suffix()),
+        format!("averageSpeed{}", units.speed_suffix()),
+        "totalCalories".to_string(),
+    ];
+    fields.extend(
+        record_names
+            .iter()
+            .map(|name| field(&format!("{}Seconds", name))),
+    );
+    fields.push("newPersonalBests".to_string());
+    line(fields)
+}
+
+// records the run didn't cover are left empty, new personal bests are listed as
+// record:window|window;record:window
+pub fn run_row(run: &RunListing, units: Units, record_names: &[String]) -> String {
+    let average_speed = match run.total_time_secs {
+        0 => 0.,
+        t => run.total_distance * 3600. / t as f32,
+    };
+    let mut fields = vec![
+        field(&run.id),
+        iso8601(run.start_time.parse().unwrap_or_default()),
+        run.total_time_secs.to_string(),
+        format!("{:.3}", units.convert(run.total_distance)),
+        format!("{:.2}", units.convert(average_speed)),
+        format!("{:.1}", run.total_calories),
+    ];
+    fields.extend(
+        record_names
+            .iter()
+            .map(|name| match run.distance_records.0.get(name) {
+                Some(Some(record)) => seconds(record.time),
+                _ => String::new(),
+            }),
+    );
+    let mut personal_bests: Vec<String> = run
+        .new_personal_bests
+        .iter()
+        .map(|(name, windows)| {
+            let windows: Vec<&str> = windows.iter().map(|w| w.name()).collect();
+            format!("{}:{}", name, windows.join("|"))
+        })
+        .collect();
+    personal_bests.sort();
+    fields.push(field(&personal_bests.join(";")));
+    line(fields)
+}
+
+// columns are in seconds, with a fraction at sub-second intervals
+fn seconds(time: Timestamp) -> String {
+    (time as f64 / 1000.).to_string()
+}
+
+pub fn series_header(units: Units) -> String {
+    line(vec![
+        "timeSeconds".to_string(),
+        format!("speed{}", units.speed_suffix()),
+        format!("distance{}", units.distance_suffix()),
+        "calories".to_string(),
+        "wallTime".to_string(),
+    ])
+}
+
+pub fn series_row(datum: &IntervalDatum, units: Units) -> String {
+    line(vec![
+        seconds(datum.time),
+        format!("{:.3}", units.convert(datum.speed)),
+        format!("{:.4}", units.convert(datum.distance)),
+        format!("{:.4}", datum.calories),
+        iso8601(datum.wall_time),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        records::RecordWindow,
+        run::{DistanceRecord, DistanceRecordSet},
+        test_fixtures::athlete,
+    };
+    use std::collections::HashMap;
+
+    #[test]
+    fn run_rows() {
+        let names = record_names(&athlete());
+        assert_eq!(names[..3], ["lap", "halfMile", "oneMile"]);
+
+        let run = RunListing {
+            id: "run".to_string(),
+            start_time: "1656202584971".to_string(),
+            total_time_secs: 1800,
+            total_calories: 350.4,
+            total_distance: 3.,
+            athlete_id: "athlete".to_string(),
+            distance_records: DistanceRecordSet(HashMap::from([
+                (
+                    "lap".to_string(),
+                    Some(DistanceRecord {
+                        start_time: 10000,
+                        end_time: 100000,
+                        start_distance: 0.1,
+                        end_distance: 0.35,
+                        time: 90000,
+                    }),
+                ),
+                ("tenKm".to_string(), None),
+            ])),
+            new_personal_bests: HashMap::from([
+                (
+                    "lap".to_string(),
+                    vec![RecordWindow::AllTime, RecordWindow::Last30Days],
+                ),
+                ("halfMile".to_string(), vec![RecordWindow::Last30Days]),
+            ]),
+        };
+        let names = vec!["lap".to_string(), "tenKm".to_string()];
+        assert_eq!(
+            run_header(Units::Metric, &names),
+            "runId,startTime,totalTimeSeconds,totalDistanceKm,averageSpeedKph,totalCalories,\
+             lapSeconds,tenKmSeconds,newPersonalBests\n"
+        );
+        assert_eq!(
+            run_row(&run, Units::Imperial, &names),
+            "run,2022-06-26T00:16:24.971Z,1800,3.000,6.00,350.4,90,,\
+             halfMile:last30Days;lap:allTime|last30Days\n"
+        );
+        assert!(run_row(&run, Units::Metric, &names).contains(",4.828,9.66,"));
+    }
+
+    #[test]
+    fn series_rows() {
+        let datum = IntervalDatum {
+            time: 42250,
+            speed: 6.,
+            raw_speed: 6.,
+            calories: 0.1234,
+            distance: 0.07,
+            wall_time: 1656202627221,
+        };
+        assert_eq!(
+            series_header(Units::Imperial),
+            "timeSeconds,speedMph,distanceMi,calories,wallTime\n"
+        );
+        assert_eq!(
+            series_row(&datum, Units::Imperial),
+            "42.25,6.000,0.0700,0.1234,2022-06-26T00:17:07.221Z\n"
+        );
+        assert_eq!(
+            series_row(&datum, Units::Metric),
+            "42.25,9.656,0.1127,0.1234,2022-06-26T00:17:07.221Z\n"
+        );
+    }
+
+    #[test]
+    fn quoted_fields() {
+        assert_eq!(field("plain"), "plain");
+        assert_eq!(field("a,b"), "\"a,b\"");
+        assert_eq!(field("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+}
@@ -0,0 +1,77 @@
+use reqwest::{Client, StatusCode};
+
+use crate::{auth::API_KEY_HEADER, run::Tickstamp};
+
+// talks to a server the way a treadmill does, for the simulator and replay tools
+#[derive(Clone)]
+pub struct DeviceClient {
+    client: Client,
+    server: String,
+    device_key: String,
+}
+
+pub struct Response {
+    pub status: StatusCode,
+    pub body: String,
+}
+
+impl Response {
+    pub fn success(self) -> Option<String> {
+        self.status.is_success().then_some(self.body)
+    }
+}
+
+impl DeviceClient {
+    pub fn new(server: &str, device_key: &str) -> DeviceClient {
+        DeviceClient {
+            client: Client::new(),
+            server: server.trim_end_matches('/').to_string(),
+            device_key: device_key.to_string(),
+        }
+    }
+
+    async fn send(&self, req: reqwest::RequestBuilder) -> Result<Response, reqwest::Error> {
+        let res = req.header(API_KEY_HEADER, &self.device_key).send().await?;
+        let status = res.status();
+        let body = res.text().await?;
+        Ok(Response { status, body })
+    }
+
+    // the body of a successful response is the new run's id; device_time is the
+    // device's millis counter as the request is sent, letting the server sync clocks
+    pub async fn new_run(
+        &self,
+        athlete_id: &str,
+        device_time: Option<Tickstamp>,
+    ) -> Result<Response, reqwest::Error> {
+        let req = self
+            .client
+            .get(format!("{}/new-run", self.server))
+            .query(&[("athlete_id", athlete_id)])
+            .query(&[("device_time", device_time)]);
+        self.send(req).await
+    }
+
+    pub async fn post_ticks(
+        &self,
+        run_id: &str,
+        ticks: &[Tickstamp],
+        device_time: Option<Tickstamp>,
+    ) -> Result<Response, reqwest::Error> {
+        let body: Vec<String> = ticks.iter().map(|t| t.to_string()).collect();
+        let req = self
+            .client
+            .post(format!("{}/run/{}", self.server, run_id))
+            .query(&[("device_time", device_time)])
+            .body(body.join(","));
+        self.send(req).await
+    }
+
+    // the body of a successful response is the run's summary
+    pub async fn finish(&self, run_id: &str) -> Result<Response, reqwest::Error> {
+        let req = self
+            .client
+            .post(format!("{}/run/{}/finish", self.server, run_id));
+        self.send(req).await
+    }
+}
@@ -0,0 +1,251 @@
+use rocket::{
+    http::Status,
+    response::{self, Responder},
+    serde::{
+        json::{serde_json::json, Json},
+        Serialize,
+    },
+    Request,
+};
+
+use crate::{
+    archive::ArchiveError,
+    athlete::InvalidAthleteError,
+    aws::{AwsError, AwsErrorKind},
+    cache::CacheError,
+    import::ImportError,
+    run::InvalidRunError,
+};
+
+// codes are part of the api and must stay stable; clients branch on them
+#[derive(Serialize, Debug, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    BadRequest,
+    Unauthorized,
+    Forbidden,
+    NotFound,
+    RunNotFound,
+    AthleteNotFound,
+    RunFinalized,
+    InvalidRequest,
+    InvalidAthlete,
+    InsufficientData,
+    RunTooShort,
+    CacheUnavailable,
+    StorageUnavailable,
+    Internal,
+}
+
+impl ErrorCode {
+    pub fn status(&self) -> Status {
+        match self {
+            ErrorCode::BadRequest => Status::BadRequest,
+            ErrorCode::Unauthorized => Status::Unauthorized,
+            ErrorCode::Forbidden => Status::Forbidden,
+            ErrorCode::NotFound | ErrorCode::RunNotFound | ErrorCode::AthleteNotFound => {
+                Status::NotFound
+            }
+            ErrorCode::RunFinalized => Status::Conflict,
+            ErrorCode::InvalidRequest
+            | ErrorCode::InvalidAthlete
+            | ErrorCode::InsufficientData
+            | ErrorCode::RunTooShort => Status::UnprocessableEntity,
+            ErrorCode::CacheUnavailable | ErrorCode::StorageUnavailable => {
+                Status::ServiceUnavailable
+            }
+            ErrorCode::Internal => Status::InternalServerError,
+        }
+    }
+
+    // whether repeating the same request later may succeed
+    pub fn retryable(&self) -> bool {
+        self.status() == Status::ServiceUnavailable
+    }
+
+    fn from_status(status: Status) -> ErrorCode {
+        match status.code {
+            400 => ErrorCode::BadRequest,
+            401 => ErrorCode::Unauthorized,
+            403 => ErrorCode::Forbidden,
+            404 => ErrorCode::NotFound,
+            409 => ErrorCode::RunFinalized,
+            422 => ErrorCode::InvalidRequest,
+            503 => ErrorCode::StorageUnavailable,
+            _ => ErrorCode::Internal,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ApiError {
+    pub code: ErrorCode,
+    pub message: String,
+    pub run_id: Option<String>,
+}
+
+impl ApiError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> ApiError {
+        ApiError {
+            code,
+            message: message.into(),
+            run_id: None,
+        }
+    }
+
+    pub fn with_run(mut self, run_id: &str) -> ApiError {
+        self.run_id = Some(run_id.to_string());
+        self
+    }
+}
+
+impl From<CacheError> for ApiError {
+    fn from(e: CacheError) -> Self {
+        ApiError::new(
+            ErrorCode::CacheUnavailable,
+            format!("cache error: {}", e.msg),
+        )
+    }
+}
+
+impl From<AwsError> for ApiError {
+    fn from(e: AwsError) -> Self {
+        match e.kind {
+            AwsErrorKind::NotFound => ApiError::new(ErrorCode::RunNotFound, e.msg),
+            AwsErrorKind::Unavailable => ApiError::new(ErrorCode::StorageUnavailable, e.msg),
+        }
+    }
+}
+
+impl From<ArchiveError> for ApiError {
+    fn from(e: ArchiveError) -> Self {
+        ApiError::new(ErrorCode::Internal, e.msg)
+    }
+}
+
+impl From<ImportError> for ApiError {
+    fn from(e: ImportError) -> Self {
+        ApiError::new(ErrorCode::InvalidRequest, e.msg)
+    }
+}
+
+impl From<InvalidRunError> for ApiError {
+    fn from(e: InvalidRunError) -> Self {
+        match e {
+            InvalidRunError::InsufficientData => ApiError::new(
+                ErrorCode::InsufficientData,
+                "not enough data to summarize run",
+            ),
+            InvalidRunError::TooShort {
+                total_time_secs,
+                min_time_secs,
+            } => ApiError::new(
+                ErrorCode::RunTooShort,
+                format!(
+                    "run lasted {}s, the minimum is {}s",
+                    total_time_secs, min_time_secs
+                ),
+            ),
+            InvalidRunError::TooLittleDistance {
+                total_distance,
+                min_distance,
+            } => ApiError::new(
+                ErrorCode::RunTooShort,
+                format!(
+                    "run covered {:.3} miles, the minimum is {} miles",
+                    total_distance, min_distance
+                ),
+            ),
+        }
+    }
+}
+
+impl From<InvalidAthleteError> for ApiError {
+    fn from(e: InvalidAthleteError) -> Self {
+        let message = match e {
+            InvalidAthleteError::MissingName => "athlete name is required".to_string(),
+            InvalidAthleteError::NonPositiveWeight => "athlete weight must be positive".to_string(),
+            InvalidAthleteError::NonPositiveRecordDistance(name) => {
+                format!("record distance {} must be positive", name)
+            }
+        };
+        ApiError::new(ErrorCode::InvalidAthlete, message)
+    }
+}
+
+impl<'r> Responder<'r, 'static> for ApiError {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let body = json!({
+            "code": self.code,
+            "message": self.message,
+            "runId": self.run_id,
+            "retryable": self.code.retryable(),
+        });
+        response::Response::build_from(Json(body).respond_to(req)?)
+            .status(self.code.status())
+            .ok()
+    }
+}
+
+// renders failures raised outside of handlers (request guards, unmatched routes,
+// malformed bodies) in the same shape as handler errors
+#[catch(default)]
+pub fn default_catcher(status: Status, _req: &Request) -> ApiError {
+    let message = status.reason().unwrap_or("unknown error").to_lowercase();
+    ApiError::new(ErrorCode::from_status(status), message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_code_status_success() {
+        assert_eq!(ErrorCode::RunFinalized.status(), Status::Conflict);
+        assert_eq!(
+            ErrorCode::InsufficientData.status(),
+            Status::UnprocessableEntity
+        );
+        assert!(ErrorCode::CacheUnavailable.retryable());
+        assert!(!ErrorCode::BadRequest.retryable());
+    }
+
+    #[test]
+    fn from_cache_error_success() {
+        let e = ApiError::from(CacheError {
+            msg: "connection refused".to_string(),
+        })
+        .with_run("abc");
+        assert_eq!(e.code, ErrorCode::CacheUnavailable);
+        assert_eq!(e.message, "cache error: connection refused");
+        assert_eq!(e.run_id, Some("abc".to_string()));
+    }
+
+    #[test]
+    fn missing_run_data_is_not_retryable() {
+        let e = ApiError::from(AwsError {
+            kind: AwsErrorKind::NotFound,
+            msg: "no data stored for run abc".to_string(),
+        });
+        assert_eq!(e.code, ErrorCode::RunNotFound);
+        assert!(!e.code.retryable());
+        let e = ApiError::from(AwsError {
+            kind: AwsErrorKind::Unavailable,
+            msg: "error reading data from s3".to_string(),
+        });
+        assert_eq!(e.code, ErrorCode::StorageUnavailable);
+        assert!(e.code.retryable());
+    }
+
+    #[test]
+    fn default_catcher_renders_json() {
+        let rocket = rocket::build().register("/", catchers![default_catcher]);
+        let client = rocket::local::blocking::Client::tracked(rocket).unwrap();
+        let response = client.get("/nowhere").dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+        assert_eq!(
+            response.into_string().unwrap(),
+            "{\"code\":\"not_found\",\"message\":\"not found\",\"retryable\":false,\"runId\":null}"
+        );
+    }
+}
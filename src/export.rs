@@ -0,0 +1,487 @@
+use crate::{
+    constants::{METERS_PER_MILE, MPH_TO_METERS_PER_SECOND},
+    run::{IntervalDatum, Summary, Timestamp},
+};
+
+const TCX_NAMESPACE: &str = "http://www.garmin.com/xmlschemas/TrainingCenterDatabase/v2";
+const TCX_SCHEMA: &str = "http://www.garmin.com/xmlschemas/TrainingCenterDatabasev2.xsd";
+const TCX_ACTIVITY_EXTENSION: &str = "http://www.garmin.com/xmlschemas/ActivityExtension/v2";
+const GPX_NAMESPACE: &str = "http://www.topografix.com/GPX/1/1";
+const GPX_SCHEMA: &str = "http://www.topografix.com/GPX/1/1/gpx.xsd";
+const GPX_TRACKPOINT_EXTENSION: &str = "http://www.garmin.com/xmlschemas/TrackPointExtension/v2";
+// our own extension elements for what gpx has no place for
+pub const GPX_RUN_EXTENSION: &str = "urn:rusty-dusty:gpx:v1";
+const XSI_NAMESPACE: &str = "http://www.w3.org/2001/XMLSchema-instance";
+
+// a stretch of the series covering one mile or kilometer, the last one may be shorter
+pub struct Split<'d> {
+    pub start_time: Timestamp,
+    pub start_distance: f32, // in miles
+    pub data: &'d [IntervalDatum],
+}
+
+impl Split<'_> {
+    fn last(&self) -> &IntervalDatum {
+        self.data.last().expect("splits are never empty")
+    }
+
+    pub fn total_time(&self) -> Timestamp {
+        self.last().time - self.start_time
+    }
+
+    pub fn distance(&self) -> f32 {
+        self.last().distance - self.start_distance
+    }
+
+    pub fn calories(&self) -> f32 {
+        self.data.iter().map(|d| d.calories).sum()
+    }
+
+    pub fn max_speed(&self) -> f32 {
+        self.data.iter().map(|d| d.speed).fold(0., f32::max)
+    }
+}
+
+// split_distance is in miles and must be positive
+pub fn splits(data: &[IntervalDatum], split_distance: f32) -> Vec<Split<'_>> {
+    let mut res = vec![];
+    let mut start = 0;
+    let mut start_time = 0;
+    let mut start_distance = 0.;
+    let mut next = split_distance;
+    for (i, datum) in data.iter().enumerate() {
+        if datum.distance >= next || i == data.len() - 1 {
+            res.push(Split {
+                start_time,
+                start_distance,
+                data: &data[start..=i],
+            });
+            start = i + 1;
+            start_time = datum.time;
+            start_distance = datum.distance;
+            while next <= datum.distance {
+                next += split_distance;
+            }
+        }
+    }
+    res
+}
+
+fn time_in_run(summary: &Summary, time: Timestamp) -> String {
+    iso8601(summary.wall_time(time))
+}
+
+// utc xsd:dateTime, days are converted after Howard Hinnant's civil_from_days
+pub fn iso8601(epoch_millis: u64) -> String {
+    let secs = epoch_millis / 1000;
+    let time_of_day = secs % 86400;
+    let z = (secs / 86400) as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        time_of_day / 3600,
+        time_of_day % 3600 / 60,
+        time_of_day % 60,
+        epoch_millis % 1000
+    )
+}
+
+// reads an xsd:dateTime back into epoch millis; times without an offset are taken as utc
+pub fn parse_iso8601(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let number = |range: std::ops::Range<usize>| value.get(range)?.parse::<i64>().ok();
+    if value.get(4..5)? != "-" || value.get(7..8)? != "-" || value.get(10..11)? != "T" {
+        return None;
+    }
+    let (year, month, day) = (number(0..4)?, number(5..7)?, number(8..10)?);
+    let (hours, minutes, seconds) = (number(11..13)?, number(14..16)?, number(17..19)?);
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hours > 23
+        || minutes > 59
+        || seconds > 60
+    {
+        return None;
+    }
+
+    let mut rest = value.get(19..)?;
+    let mut millis = 0;
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let digits = fraction
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(fraction.len());
+        let padded = format!("{:0<3}", &fraction[..digits]);
+        millis = padded.get(..3)?.parse::<i64>().ok()?;
+        rest = &fraction[digits..];
+    }
+    let offset_minutes = match rest {
+        "" | "Z" => 0,
+        _ => {
+            let sign = match rest.get(..1)? {
+                "+" => 1,
+                "-" => -1,
+                _ => return None,
+            };
+            let hours = rest.get(1..3)?.parse::<i64>().ok()?;
+            let minutes = rest.get(4..6)?.parse::<i64>().ok()?;
+            sign * (hours * 60 + minutes)
+        }
+    };
+
+    // the inverse of civil_from_days above
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let year_of_era = y - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+    let secs = days * 86400 + hours * 3600 + minutes * 60 + seconds - offset_minutes * 60;
+    u64::try_from(secs * 1000 + millis).ok()
+}
+
+// one lap per split, each trackpoint carrying distance and, as an extension, speed
+pub fn tcx(summary: &Summary, split_distance: f32) -> String {
+    let start = summary.wall_time(0);
+    let splits = splits(&summary.interval_data, split_distance);
+    let mut out = String::new();
+    out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str(&format!(
+        "<TrainingCenterDatabase xmlns=\"{}\" xmlns:ns3=\"{}\" xmlns:xsi=\"{}\" xsi:schemaLocation=\"{} {}\">\n",
+        TCX_NAMESPACE, TCX_ACTIVITY_EXTENSION, XSI_NAMESPACE, TCX_NAMESPACE, TCX_SCHEMA
+    ));
+    out.push_str("  <Activities>\n");
+    out.push_str("    <Activity Sport=\"Running\">\n");
+    out.push_str(&format!("      <Id>{}</Id>\n", iso8601(start)));
+    for (i, split) in splits.iter().enumerate() {
+        let trigger = if i + 1 < splits.len() {
+            "Distance"
+        } else {
+            "Manual"
+        };
+        let average_speed = match split.total_time() {
+            0 => 0.,
+            t => split.distance() * METERS_PER_MILE / (t as f32 / 1000.),
+        };
+        out.push_str(&format!(
+            "      <Lap StartTime=\"{}\">\n",
+            time_in_run(summary, split.start_time)
+        ));
+        out.push_str(&format!(
+            "        <TotalTimeSeconds>{}</TotalTimeSeconds>\n",
+            split.total_time() as f32 / 1000.
+        ));
+        out.push_str(&format!(
+            "        <DistanceMeters>{:.2}</DistanceMeters>\n",
+            split.distance() * METERS_PER_MILE
+        ));
+        out.push_str(&format!(
+            "        <MaximumSpeed>{:.3}</MaximumSpeed>\n",
+            split.max_speed() * MPH_TO_METERS_PER_SECOND
+        ));
+        out.push_str(&format!(
+            "        <Calories>{}</Calories>\n",
+            split.calories().round() as u16
+        ));
+        out.push_str("        <Intensity>Active</Intensity>\n");
+        out.push_str(&format!(
+            "        <TriggerMethod>{}</TriggerMethod>\n",
+            trigger
+        ));
+        out.push_str("        <Track>\n");
+        for datum in split.data {
+            out.push_str("          <Trackpoint>\n");
+            out.push_str(&format!(
+                "            <Time>{}</Time>\n",
+                time_in_run(summary, datum.time)
+            ));
+            out.push_str(&format!(
+                "            <DistanceMeters>{:.2}</DistanceMeters>\n",
+                datum.distance * METERS_PER_MILE
+            ));
+            out.push_str(&format!(
+                "            <Extensions><ns3:TPX><ns3:Speed>{:.3}</ns3:Speed></ns3:TPX></Extensions>\n",
+                datum.speed * MPH_TO_METERS_PER_SECOND
+            ));
+            out.push_str("          </Trackpoint>\n");
+        }
+        out.push_str("        </Track>\n");
+        out.push_str(&format!(
+            "        <Extensions><ns3:LX><ns3:AvgSpeed>{:.3}</ns3:AvgSpeed></ns3:LX></Extensions>\n",
+            average_speed
+        ));
+        out.push_str("      </Lap>\n");
+    }
+    out.push_str("    </Activity>\n");
+    out.push_str("  </Activities>\n");
+    out.push_str("</TrainingCenterDatabase>\n");
+    out
+}
+
+// a treadmill doesn't move, so every trackpoint sits at the same position and the
+// run itself lives in the extensions
+pub fn gpx(summary: &Summary) -> String {
+    let start = summary.wall_time(0);
+    let mut out = String::new();
+    out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str(&format!(
+        "<gpx version=\"1.1\" creator=\"rusty-dusty\" xmlns=\"{}\" xmlns:gpxtpx=\"{}\" xmlns:rd=\"{}\" xmlns:xsi=\"{}\" xsi:schemaLocation=\"{} {}\">\n",
+        GPX_NAMESPACE, GPX_TRACKPOINT_EXTENSION, GPX_RUN_EXTENSION, XSI_NAMESPACE, GPX_NAMESPACE, GPX_SCHEMA
+    ));
+    out.push_str(&format!(
+        "  <metadata><time>{}</time></metadata>\n",
+        iso8601(start)
+    ));
+    out.push_str("  <trk>\n");
+    out.push_str("    <name>Treadmill run</name>\n");
+    out.push_str("    <type>running</type>\n");
+    out.push_str(&format!(
+        "    <extensions><rd:totalTime>{}</rd:totalTime><rd:totalDistance>{:.2}</rd:totalDistance><rd:totalCalories>{:.1}</rd:totalCalories></extensions>\n",
+        summary.total_time_secs,
+        summary.total_distance * METERS_PER_MILE,
+        summary.total_calories
+    ));
+    out.push_str("    <trkseg>\n");
+    let mut calories = 0.;
+    for datum in &summary.interval_data {
+        calories += datum.calories;
+        out.push_str("      <trkpt lat=\"0\" lon=\"0\">\n");
+        out.push_str(&format!(
+            "        <time>{}</time>\n",
+            time_in_run(summary, datum.time)
+        ));
+        out.push_str(&format!(
+            "        <extensions><gpxtpx:TrackPointExtension><gpxtpx:speed>{:.3}</gpxtpx:speed></gpxtpx:TrackPointExtension><rd:distance>{:.2}</rd:distance><rd:calories>{:.2}</rd:calories></extensions>\n",
+            datum.speed * MPH_TO_METERS_PER_SECOND,
+            datum.distance * METERS_PER_MILE,
+            calories
+        ));
+        out.push_str("      </trkpt>\n");
+    }
+    out.push_str("    </trkseg>\n");
+    out.push_str("  </trk>\n");
+    out.push_str("</gpx>\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::steady_summary;
+    use regex::Regex;
+    use roxmltree::{Document, Node};
+    use std::{
+        io::Write,
+        path::Path,
+        process::{Command, Stdio},
+    };
+
+    const DATE_TIME: &str = r"^\d{4}-\d{2}-\d{2}T\d{2}:\d{2}:\d{2}\.\d{3}Z$";
+
+    // the published schemas, see fixtures/schemas/README.md
+    const SCHEMAS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/schemas");
+
+    fn children<'a, 'i>(node: Node<'a, 'i>) -> Vec<Node<'a, 'i>> {
+        node.children().filter(|n| n.is_element()).collect()
+    }
+
+    fn names<'a>(node: Node<'a, '_>) -> Vec<&'a str> {
+        children(node).iter().map(|n| n.tag_name().name()).collect()
+    }
+
+    fn child<'a, 'i>(node: Node<'a, 'i>, name: &str) -> Node<'a, 'i> {
+        children(node)
+            .into_iter()
+            .find(|n| n.tag_name().name() == name)
+            .unwrap_or_else(|| panic!("no <{}> in <{}>", name, node.tag_name().name()))
+    }
+
+    fn number(node: Node, name: &str) -> f32 {
+        child(node, name).text().unwrap().parse().unwrap()
+    }
+
+    #[test]
+    fn iso8601_dates() {
+        assert_eq!(iso8601(0), "1970-01-01T00:00:00.000Z");
+        assert_eq!(iso8601(1656202584971), "2022-06-26T00:16:24.971Z");
+        assert_eq!(iso8601(951782400000), "2000-02-29T00:00:00.000Z");
+        assert_eq!(iso8601(4107542399999), "2100-02-28T23:59:59.999Z");
+    }
+
+    #[test]
+    fn parse_iso8601_dates() {
+        for millis in [0, 951782400000, 1656202584971, 4107542399999] {
+            assert_eq!(parse_iso8601(&iso8601(millis)), Some(millis));
+        }
+        assert_eq!(parse_iso8601("2022-06-26T00:16:24Z"), Some(1656202584000));
+        assert_eq!(
+            parse_iso8601("2022-06-26T02:16:24.5+02:00"),
+            Some(1656202584500)
+        );
+        assert_eq!(
+            parse_iso8601("2022-06-25T20:16:24.971-04:00"),
+            Some(1656202584971)
+        );
+        assert_eq!(parse_iso8601("2022-06-26 00:16:24Z"), None);
+        assert_eq!(parse_iso8601("yesterday"), None);
+    }
+
+    #[test]
+    fn split_by_distance() {
+        let summary = steady_summary(900, 10.);
+        let splits = splits(&summary.interval_data, 1.);
+        assert_eq!(splits.len(), 3);
+        assert_eq!(
+            splits.iter().map(|s| s.total_time()).collect::<Vec<_>>(),
+            vec![360_000, 360_000, 180_000]
+        );
+        assert!((splits[1].distance() - 1.).abs() < 0.001);
+        assert!((splits[2].distance() - 0.5).abs() < 0.001);
+        assert!((splits[0].calories() - 72.).abs() < 0.01);
+        assert_eq!(splits[2].max_speed(), 10.);
+        assert_eq!(splits.iter().map(|s| s.data.len()).sum::<usize>(), 900);
+    }
+
+    // element order and required content follow TrainingCenterDatabasev2.xsd
+    #[test]
+    fn tcx_follows_schema() {
+        let xml = tcx(&steady_summary(900, 10.), 1.);
+        let doc = Document::parse(&xml).unwrap();
+        let date_time = Regex::new(DATE_TIME).unwrap();
+        let root = doc.root_element();
+        assert_eq!(root.tag_name().name(), "TrainingCenterDatabase");
+        assert_eq!(root.tag_name().namespace(), Some(TCX_NAMESPACE));
+        assert_eq!(names(root), vec!["Activities"]);
+
+        let activity = child(child(root, "Activities"), "Activity");
+        assert_eq!(activity.attribute("Sport"), Some("Running"));
+        assert!(date_time.is_match(child(activity, "Id").text().unwrap()));
+        assert_eq!(names(activity), vec!["Id", "Lap", "Lap", "Lap"]);
+
+        let laps = children(activity).into_iter().skip(1).collect::<Vec<_>>();
+        let mut total_distance = 0.;
+        let mut total_points = 0;
+        for (i, lap) in laps.iter().enumerate() {
+            assert!(date_time.is_match(lap.attribute("StartTime").unwrap()));
+            assert_eq!(
+                names(*lap),
+                vec![
+                    "TotalTimeSeconds",
+                    "DistanceMeters",
+                    "MaximumSpeed",
+                    "Calories",
+                    "Intensity",
+                    "TriggerMethod",
+                    "Track",
+                    "Extensions"
+                ]
+            );
+            let trigger = child(*lap, "TriggerMethod").text().unwrap();
+            assert_eq!(trigger, if i < 2 { "Distance" } else { "Manual" });
+            child(*lap, "Calories")
+                .text()
+                .unwrap()
+                .parse::<u16>()
+                .unwrap();
+            total_distance += number(*lap, "DistanceMeters");
+            for point in children(child(*lap, "Track")) {
+                assert_eq!(names(point), vec!["Time", "DistanceMeters", "Extensions"]);
+                assert!(date_time.is_match(child(point, "Time").text().unwrap()));
+                let tpx = child(child(point, "Extensions"), "TPX");
+                assert_eq!(tpx.tag_name().namespace(), Some(TCX_ACTIVITY_EXTENSION));
+                assert!((number(tpx, "Speed") - 4.4704).abs() < 0.001);
+                total_points += 1;
+            }
+        }
+        assert_eq!(total_points, 900);
+        assert!((total_distance - 2.5 * METERS_PER_MILE).abs() < 0.1);
+        assert_eq!(child(laps[0], "Calories").text(), Some("72"));
+    }
+
+    // element order and required attributes follow gpx.xsd
+    #[test]
+    fn gpx_follows_schema() {
+        let xml = gpx(&steady_summary(900, 10.));
+        let doc = Document::parse(&xml).unwrap();
+        let date_time = Regex::new(DATE_TIME).unwrap();
+        let root = doc.root_element();
+        assert_eq!(root.tag_name().name(), "gpx");
+        assert_eq!(root.tag_name().namespace(), Some(GPX_NAMESPACE));
+        assert_eq!(root.attribute("version"), Some("1.1"));
+        assert!(root.attribute("creator").is_some());
+        assert_eq!(names(root), vec!["metadata", "trk"]);
+
+        let trk = child(root, "trk");
+        assert_eq!(names(trk), vec!["name", "type", "extensions", "trkseg"]);
+        assert_eq!(number(child(trk, "extensions"), "totalTime"), 900.);
+
+        let points = children(child(trk, "trkseg"));
+        assert_eq!(points.len(), 900);
+        for point in &points {
+            assert_eq!(point.attribute("lat"), Some("0"));
+            assert_eq!(point.attribute("lon"), Some("0"));
+            assert_eq!(names(*point), vec!["time", "extensions"]);
+            assert!(date_time.is_match(child(*point, "time").text().unwrap()));
+            let extensions = child(*point, "extensions");
+            assert_eq!(
+                names(extensions),
+                vec!["TrackPointExtension", "distance", "calories"]
+            );
+            let tpx = child(extensions, "TrackPointExtension");
+            assert_eq!(tpx.tag_name().namespace(), Some(GPX_TRACKPOINT_EXTENSION));
+            assert!((number(tpx, "speed") - 4.4704).abs() < 0.001);
+            assert_eq!(
+                child(extensions, "distance").tag_name().namespace(),
+                Some(GPX_RUN_EXTENSION)
+            );
+        }
+        let last = child(*points.last().unwrap(), "extensions");
+        assert!((number(last, "calories") - 180.).abs() < 0.1);
+        assert_eq!(
+            child(*points.last().unwrap(), "time").text(),
+            Some("2022-06-26T00:31:24.971Z")
+        );
+    }
+
+    // xmllint reads the document from stdin and fails on any schema violation
+    fn validate(xml: &str, schema: &str) {
+        let schema = format!("{}/{}", SCHEMAS, schema);
+        assert!(Path::new(&schema).exists(), "{} is not checked in", schema);
+        let mut xmllint = Command::new("xmllint")
+            .args(["--noout", "--nonet", "--schema", &schema, "-"])
+            .stdin(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("xmllint is installed");
+        xmllint
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(xml.as_bytes())
+            .unwrap();
+        let output = xmllint.wait_with_output().unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    #[test]
+    #[ignore = "needs the published schemas in fixtures/schemas"]
+    fn exports_validate_against_published_schemas() {
+        let summary = steady_summary(900, 10.);
+        validate(&tcx(&summary, 1.), "TrainingCenterDatabasev2.xsd");
+        validate(&gpx(&summary), "gpx.xsd");
+    }
+}
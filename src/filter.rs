@@ -0,0 +1,299 @@
+use crate::constants::{
+    KALMAN_MEASUREMENT_NOISE, KALMAN_PROCESS_NOISE, SPEED_FILTER, SPEED_FILTER_WINDOW,
+    SPEED_SMOOTHING,
+};
+use serde::Deserialize;
+
+// smooths the raw per-interval speeds of a whole run; filters may look ahead,
+// since runs are summarized once all their ticks are in
+pub trait SpeedFilter {
+    fn smooth(&self, raw: &[f32]) -> Vec<f32>;
+}
+
+// exponential moving average, starting from standing still
+pub struct Ema {
+    pub smoothing: f32, // weight of the previous value, 0 keeps the raw speed
+}
+
+impl SpeedFilter for Ema {
+    fn smooth(&self, raw: &[f32]) -> Vec<f32> {
+        let mut speed = 0.;
+        raw.iter()
+            .map(|r| {
+                speed = r * (1. - self.smoothing) + self.smoothing * speed;
+                speed
+            })
+            .collect()
+    }
+}
+
+// the values within window / 2 intervals on either side, fewer at the ends
+fn centered(raw: &[f32], window: usize) -> impl Iterator<Item = &[f32]> {
+    let half = window / 2;
+    (0..raw.len()).map(move |i| &raw[i.saturating_sub(half)..raw.len().min(i + half + 1)])
+}
+
+pub struct MovingAverage {
+    pub window: usize, // in intervals, rounded up to odd
+}
+
+impl SpeedFilter for MovingAverage {
+    fn smooth(&self, raw: &[f32]) -> Vec<f32> {
+        centered(raw, self.window)
+            .map(|w| w.iter().sum::<f32>() / w.len() as f32)
+            .collect()
+    }
+}
+
+// keeps steps sharp while dropping single interval spikes
+pub struct Median {
+    pub window: usize, // in intervals, rounded up to odd
+}
+
+impl SpeedFilter for Median {
+    fn smooth(&self, raw: &[f32]) -> Vec<f32> {
+        centered(raw, self.window)
+            .map(|w| {
+                let mut w = w.to_vec();
+                w.sort_by(f32::total_cmp);
+                match w.len() % 2 {
+                    1 => w[w.len() / 2],
+                    _ => (w[w.len() / 2 - 1] + w[w.len() / 2]) / 2.,
+                }
+            })
+            .collect()
+    }
+}
+
+// speed as a random walk measured with noise; trusts measurements more the
+// larger process_noise is relative to measurement_noise
+pub struct Kalman {
+    pub process_noise: f32,     // variance of speed changes per interval, in mph²
+    pub measurement_noise: f32, // variance of the raw speed, in mph²
+}
+
+impl SpeedFilter for Kalman {
+    fn smooth(&self, raw: &[f32]) -> Vec<f32> {
+        let mut estimate = match raw.first() {
+            Some(r) => *r,
+            None => return vec![],
+        };
+        let mut variance = self.measurement_noise;
+        raw.iter()
+            .map(|r| {
+                variance += self.process_noise;
+                let gain = variance / (variance + self.measurement_noise);
+                estimate += gain * (r - estimate);
+                variance *= 1. - gain;
+                estimate
+            })
+            .collect()
+    }
+}
+
+fn default_smoothing() -> f32 {
+    SPEED_SMOOTHING
+}
+
+fn default_window() -> usize {
+    SPEED_FILTER_WINDOW
+}
+
+fn default_process_noise() -> f32 {
+    KALMAN_PROCESS_NOISE
+}
+
+fn default_measurement_noise() -> f32 {
+    KALMAN_MEASUREMENT_NOISE
+}
+
+// which filter summaries use, with its parameters; configured as a table like
+// { name = "median", window = 7 }, leaving out parameters keeps their defaults
+#[derive(Debug, PartialEq, Clone, Copy, Deserialize)]
+#[serde(tag = "name", rename_all = "kebab-case")]
+pub enum SpeedFilterConfig {
+    Ema {
+        #[serde(default = "default_smoothing")]
+        smoothing: f32,
+    },
+    MovingAverage {
+        #[serde(default = "default_window")]
+        window: usize,
+    },
+    Median {
+        #[serde(default = "default_window")]
+        window: usize,
+    },
+    Kalman {
+        #[serde(default = "default_process_noise")]
+        process_noise: f32,
+        #[serde(default = "default_measurement_noise")]
+        measurement_noise: f32,
+    },
+}
+
+impl SpeedFilterConfig {
+    // the named filter with the default parameters from constants; configured
+    // parameters come from the [analysis] table instead
+    pub fn from_name(name: &str) -> Option<SpeedFilterConfig> {
+        match name {
+            "ema" => Some(SpeedFilterConfig::Ema {
+                smoothing: SPEED_SMOOTHING,
+            }),
+            "moving-average" => Some(SpeedFilterConfig::MovingAverage {
+                window: SPEED_FILTER_WINDOW,
+            }),
+            "median" => Some(SpeedFilterConfig::Median {
+                window: SPEED_FILTER_WINDOW,
+            }),
+            "kalman" => Some(SpeedFilterConfig::Kalman {
+                process_noise: KALMAN_PROCESS_NOISE,
+                measurement_noise: KALMAN_MEASUREMENT_NOISE,
+            }),
+            _ => None,
+        }
+    }
+
+    // the parameters' problems, if any
+    pub fn validate(&self) -> Result<(), String> {
+        match *self {
+            SpeedFilterConfig::Ema { smoothing } if !(0. ..1.).contains(&smoothing) => {
+                Err(format!(
+                    "ema smoothing must be at least 0 and below 1, got {}",
+                    smoothing
+                ))
+            }
+            SpeedFilterConfig::MovingAverage { window: 0 }
+            | SpeedFilterConfig::Median { window: 0 } => {
+                Err(format!("{} window must be at least 1", self.name()))
+            }
+            SpeedFilterConfig::Kalman {
+                process_noise,
+                measurement_noise,
+            } if !(process_noise > 0. && measurement_noise > 0.) => Err(format!(
+                "kalman noises must be positive, got {} and {}",
+                process_noise, measurement_noise
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SpeedFilterConfig::Ema { .. } => "ema",
+            SpeedFilterConfig::MovingAverage { .. } => "moving-average",
+            SpeedFilterConfig::Median { .. } => "median",
+            SpeedFilterConfig::Kalman { .. } => "kalman",
+        }
+    }
+
+    pub fn filter(&self) -> Box<dyn SpeedFilter> {
+        match *self {
+            SpeedFilterConfig::Ema { smoothing } => Box::new(Ema { smoothing }),
+            SpeedFilterConfig::MovingAverage { window } => Box::new(MovingAverage { window }),
+            SpeedFilterConfig::Median { window } => Box::new(Median { window }),
+            SpeedFilterConfig::Kalman {
+                process_noise,
+                measurement_noise,
+            } => Box::new(Kalman {
+                process_noise,
+                measurement_noise,
+            }),
+        }
+    }
+}
+
+impl Default for SpeedFilterConfig {
+    fn default() -> SpeedFilterConfig {
+        SpeedFilterConfig::from_name(SPEED_FILTER).expect("SPEED_FILTER names a filter")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NAMES: [&str; 4] = ["ema", "moving-average", "median", "kalman"];
+
+    // 10 intervals at 5 mph, then 10 at 9 mph
+    fn step() -> Vec<f32> {
+        (0..20).map(|i| if i < 10 { 5. } else { 9. }).collect()
+    }
+
+    #[test]
+    fn filters_by_name() {
+        for name in NAMES {
+            assert_eq!(SpeedFilterConfig::from_name(name).unwrap().name(), name);
+        }
+        assert_eq!(SpeedFilterConfig::from_name("gaussian"), None);
+        assert_eq!(SpeedFilterConfig::default().name(), SPEED_FILTER);
+    }
+
+    #[test]
+    fn ema_smoothing_is_below_one() {
+        for smoothing in [0., 0.5, 0.99] {
+            assert_eq!(SpeedFilterConfig::Ema { smoothing }.validate(), Ok(()));
+        }
+        for smoothing in [-0.1, 1., 1.5, f32::NAN] {
+            assert!(SpeedFilterConfig::Ema { smoothing }.validate().is_err());
+        }
+    }
+
+    #[test]
+    fn ema_matches_previous_smoothing() {
+        let smoothed = Ema { smoothing: 0.5 }.smooth(&[4., 4., 8.]);
+        assert_eq!(smoothed, vec![2., 3., 5.5]);
+    }
+
+    #[test]
+    fn centered_filters_keep_steps_in_place() {
+        let raw = step();
+        let average = MovingAverage { window: 5 }.smooth(&raw);
+        assert_eq!(average[7], 5.);
+        assert_eq!(average[10], (5. + 5. + 9. + 9. + 9.) / 5.);
+        assert_eq!(average[12], 9.);
+        // the exponential average is still catching up two intervals in
+        let ema = Ema { smoothing: 0.5 }.smooth(&raw);
+        assert!(ema[11] < 8.5);
+
+        let median = Median { window: 5 }.smooth(&raw);
+        assert_eq!(median, raw);
+    }
+
+    #[test]
+    fn median_drops_spikes() {
+        let mut raw = vec![6.; 9];
+        raw[4] = 20.;
+        raw[6] = 0.;
+        assert_eq!(Median { window: 3 }.smooth(&raw), vec![6.; 9]);
+        assert_eq!(Median { window: 4 }.smooth(&[1., 3.]), vec![2., 2.]);
+    }
+
+    #[test]
+    fn kalman_converges() {
+        let smoothed = Kalman {
+            process_noise: 0.5,
+            measurement_noise: 1.,
+        }
+        .smooth(&step());
+        assert_eq!(smoothed[9], 5.);
+        assert!(smoothed[10] > 5. && smoothed[10] < 9.);
+        assert!((smoothed[19] - 9.).abs() < 0.01);
+    }
+
+    #[test]
+    fn filters_keep_constant_speeds_and_lengths() {
+        for name in NAMES {
+            let filter = SpeedFilterConfig::from_name(name).unwrap().filter();
+            assert_eq!(filter.smooth(&[]), Vec::<f32>::new());
+            let smoothed = filter.smooth(&[6.; 30]);
+            assert_eq!(smoothed.len(), 30);
+            // the exponential average starts from standing still
+            assert!(
+                smoothed[10..].iter().all(|s| (s - 6.).abs() < 0.01),
+                "{}",
+                name
+            );
+        }
+    }
+}
@@ -0,0 +1,400 @@
+use crate::{
+    constants::{METERS_PER_MILE, MPH_TO_METERS_PER_SECOND},
+    export::splits,
+    run::{Summary, Timestamp},
+};
+
+const PROTOCOL_VERSION: u8 = 0x10; // 1.0
+const PROFILE_VERSION: u16 = 2132; // 21.32
+const HEADER_SIZE: u8 = 14;
+// fit timestamps count seconds from 1989-12-31T00:00:00Z
+const FIT_EPOCH_OFFSET: u64 = 631065600;
+
+// global message numbers
+const FILE_ID: u16 = 0;
+const SESSION: u16 = 18;
+const LAP: u16 = 19;
+const RECORD: u16 = 20;
+const ACTIVITY: u16 = 34;
+
+// profile values
+const FILE_TYPE_ACTIVITY: u64 = 4;
+const MANUFACTURER_DEVELOPMENT: u64 = 255;
+const SPORT_RUNNING: u64 = 1;
+const SUB_SPORT_TREADMILL: u64 = 1;
+const EVENT_ACTIVITY: u64 = 26;
+const EVENT_SESSION: u64 = 8;
+const EVENT_LAP: u64 = 9;
+const EVENT_TYPE_STOP: u64 = 1;
+const LAP_TRIGGER_MANUAL: u64 = 0;
+const LAP_TRIGGER_DISTANCE: u64 = 2;
+const ACTIVITY_TYPE_MANUAL: u64 = 0;
+
+#[derive(Clone, Copy)]
+enum BaseType {
+    Enum,
+    Uint16,
+    Uint32,
+}
+
+impl BaseType {
+    fn id(&self) -> u8 {
+        match self {
+            BaseType::Enum => 0x00,
+            BaseType::Uint16 => 0x84,
+            BaseType::Uint32 => 0x86,
+        }
+    }
+
+    fn size(&self) -> u8 {
+        match self {
+            BaseType::Enum => 1,
+            BaseType::Uint16 => 2,
+            BaseType::Uint32 => 4,
+        }
+    }
+}
+
+use BaseType::{Enum, Uint16, Uint32};
+
+// field number and type, in the order values are written
+type FieldDefinition = (u8, BaseType);
+
+const FILE_ID_FIELDS: [FieldDefinition; 4] = [(0, Enum), (1, Uint16), (2, Uint16), (4, Uint32)];
+// timestamp, distance (cm), speed (mm/s), calories (kcal, accumulated)
+const RECORD_FIELDS: [FieldDefinition; 4] = [(253, Uint32), (5, Uint32), (6, Uint16), (33, Uint16)];
+// timestamp, start time, elapsed and timer time (ms), distance (cm), calories,
+// average and max speed (mm/s), event, event type, sport, sub sport, trigger, index
+const LAP_FIELDS: [FieldDefinition; 14] = [
+    (253, Uint32),
+    (2, Uint32),
+    (7, Uint32),
+    (8, Uint32),
+    (9, Uint32),
+    (11, Uint16),
+    (13, Uint16),
+    (14, Uint16),
+    (0, Enum),
+    (1, Enum),
+    (25, Enum),
+    (39, Enum),
+    (24, Enum),
+    (254, Uint16),
+];
+// timestamp, start time, elapsed and timer time (ms), distance (cm), calories,
+// average and max speed (mm/s), event, event type, sport, sub sport, first lap, laps
+const SESSION_FIELDS: [FieldDefinition; 14] = [
+    (253, Uint32),
+    (2, Uint32),
+    (7, Uint32),
+    (8, Uint32),
+    (9, Uint32),
+    (11, Uint16),
+    (14, Uint16),
+    (15, Uint16),
+    (0, Enum),
+    (1, Enum),
+    (5, Enum),
+    (6, Enum),
+    (25, Uint16),
+    (26, Uint16),
+];
+// timestamp, timer time (ms), sessions, type, event, event type
+const ACTIVITY_FIELDS: [FieldDefinition; 6] = [
+    (253, Uint32),
+    (0, Uint32),
+    (1, Uint16),
+    (2, Enum),
+    (3, Enum),
+    (4, Enum),
+];
+
+const CRC_TABLE: [u16; 16] = [
+    0x0000, 0xCC01, 0xD801, 0x1400, 0xF001, 0x3C00, 0x2800, 0xE401, 0xA001, 0x6C00, 0x7800, 0xB401,
+    0x5000, 0x9C01, 0x8801, 0x4400,
+];
+
+fn crc(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0, |mut crc, byte| {
+        for nibble in [byte & 0xF, byte >> 4] {
+            let tmp = CRC_TABLE[(crc & 0xF) as usize];
+            crc = (crc >> 4) & 0x0FFF;
+            crc = crc ^ tmp ^ CRC_TABLE[nibble as usize];
+        }
+        crc
+    })
+}
+
+// each message type gets its own local number, so every definition is written once
+struct FitWriter {
+    records: Vec<u8>,
+    definitions: Vec<(u8, &'static [FieldDefinition])>,
+}
+
+impl FitWriter {
+    fn new() -> FitWriter {
+        FitWriter {
+            records: vec![],
+            definitions: vec![],
+        }
+    }
+
+    fn define(&mut self, global: u16, fields: &'static [FieldDefinition]) -> u8 {
+        let local = self.definitions.len() as u8;
+        self.records.push(0x40 | local);
+        self.records.push(0); // reserved
+        self.records.push(0); // little endian
+        self.records.extend_from_slice(&global.to_le_bytes());
+        self.records.push(fields.len() as u8);
+        for (number, base_type) in fields {
+            self.records
+                .extend([*number, base_type.size(), base_type.id()]);
+        }
+        self.definitions.push((local, fields));
+        local
+    }
+
+    fn write(&mut self, local: u8, values: &[u64]) {
+        let (_, fields) = self.definitions[local as usize];
+        self.records.push(local);
+        for ((_, base_type), value) in fields.iter().zip(values) {
+            let bytes = value.to_le_bytes();
+            self.records
+                .extend_from_slice(&bytes[..base_type.size() as usize]);
+        }
+    }
+
+    fn finish(self) -> Vec<u8> {
+        let mut out = vec![HEADER_SIZE, PROTOCOL_VERSION];
+        out.extend_from_slice(&PROFILE_VERSION.to_le_bytes());
+        out.extend_from_slice(&(self.records.len() as u32).to_le_bytes());
+        out.extend_from_slice(b".FIT");
+        out.extend_from_slice(&crc(&out).to_le_bytes());
+        out.extend_from_slice(&self.records);
+        out.extend_from_slice(&crc(&out).to_le_bytes());
+        out
+    }
+}
+
+fn centimeters(miles: f32) -> u64 {
+    (miles * METERS_PER_MILE * 100.).round() as u64
+}
+
+fn millimeters_per_second(mph: f32) -> u64 {
+    (mph * MPH_TO_METERS_PER_SECOND * 1000.).round() as u64
+}
+
+// in mm/s from miles and millis
+fn average_speed(distance: f32, millis: u64) -> u64 {
+    match millis {
+        0 => 0,
+        t => (distance * METERS_PER_MILE * 1_000_000. / t as f32).round() as u64,
+    }
+}
+
+// fit timestamps are whole seconds since the fit epoch
+fn fit_time(summary: &Summary, time: Timestamp) -> u64 {
+    (summary.wall_time(time) / 1000).saturating_sub(FIT_EPOCH_OFFSET)
+}
+
+// an activity with a record per datum, a lap per split and one treadmill session
+pub fn encode(summary: &Summary, split_distance: f32) -> Vec<u8> {
+    let start = fit_time(summary, 0);
+    let end = start + summary.total_time_secs as u64;
+    let mut fit = FitWriter::new();
+
+    let file_id = fit.define(FILE_ID, &FILE_ID_FIELDS);
+    fit.write(
+        file_id,
+        &[FILE_TYPE_ACTIVITY, MANUFACTURER_DEVELOPMENT, 0, start],
+    );
+
+    let record = fit.define(RECORD, &RECORD_FIELDS);
+    let mut calories = 0.;
+    for datum in &summary.interval_data {
+        calories += datum.calories;
+        fit.write(
+            record,
+            &[
+                fit_time(summary, datum.time),
+                centimeters(datum.distance),
+                millimeters_per_second(datum.speed),
+                calories.round() as u64,
+            ],
+        );
+    }
+
+    let lap = fit.define(LAP, &LAP_FIELDS);
+    let splits = splits(&summary.interval_data, split_distance);
+    for (i, split) in splits.iter().enumerate() {
+        let trigger = match i + 1 < splits.len() {
+            true => LAP_TRIGGER_DISTANCE,
+            false => LAP_TRIGGER_MANUAL,
+        };
+        let time = split.total_time() as u64;
+        fit.write(
+            lap,
+            &[
+                fit_time(summary, split.start_time + split.total_time()),
+                fit_time(summary, split.start_time),
+                time,
+                time,
+                centimeters(split.distance()),
+                split.calories().round() as u64,
+                average_speed(split.distance(), time),
+                millimeters_per_second(split.max_speed()),
+                EVENT_LAP,
+                EVENT_TYPE_STOP,
+                SPORT_RUNNING,
+                SUB_SPORT_TREADMILL,
+                trigger,
+                i as u64,
+            ],
+        );
+    }
+
+    let max_speed = summary
+        .interval_data
+        .iter()
+        .map(|d| d.speed)
+        .fold(0., f32::max);
+    let total_time = summary.total_time_secs as u64 * 1000;
+    let session = fit.define(SESSION, &SESSION_FIELDS);
+    fit.write(
+        session,
+        &[
+            end,
+            start,
+            total_time,
+            total_time,
+            centimeters(summary.total_distance),
+            summary.total_calories.round() as u64,
+            average_speed(summary.total_distance, total_time),
+            millimeters_per_second(max_speed),
+            EVENT_SESSION,
+            EVENT_TYPE_STOP,
+            SPORT_RUNNING,
+            SUB_SPORT_TREADMILL,
+            0,
+            splits.len() as u64,
+        ],
+    );
+
+    let activity = fit.define(ACTIVITY, &ACTIVITY_FIELDS);
+    fit.write(
+        activity,
+        &[
+            end,
+            total_time,
+            1,
+            ACTIVITY_TYPE_MANUAL,
+            EVENT_ACTIVITY,
+            EVENT_TYPE_STOP,
+        ],
+    );
+    fit.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::steady_summary;
+    use std::collections::HashMap;
+
+    // global message number and its fields by number
+    type Message = (u16, HashMap<u8, u64>);
+
+    // reads back normal-header messages, as written by FitWriter
+    fn decode(bytes: &[u8]) -> Vec<Message> {
+        assert_eq!(bytes[0], HEADER_SIZE);
+        assert_eq!(&bytes[8..12], b".FIT");
+        assert_eq!(crc(&bytes[..14]), 0);
+        assert_eq!(crc(bytes), 0);
+        let data_size = u32::from_le_bytes(bytes[4..8].try_into().unwrap()) as usize;
+        assert_eq!(bytes.len(), 14 + data_size + 2);
+
+        let mut definitions: HashMap<u8, (u16, Vec<(u8, u8)>)> = HashMap::new();
+        let mut messages = vec![];
+        let mut i = 14;
+        while i < 14 + data_size {
+            let header = bytes[i];
+            let local = header & 0x0F;
+            i += 1;
+            if header & 0x40 != 0 {
+                let global = u16::from_le_bytes([bytes[i + 2], bytes[i + 3]]);
+                let count = bytes[i + 4] as usize;
+                i += 5;
+                let fields = (0..count)
+                    .map(|f| (bytes[i + 3 * f], bytes[i + 3 * f + 1]))
+                    .collect();
+                i += 3 * count;
+                definitions.insert(local, (global, fields));
+            } else {
+                let (global, fields) = &definitions[&local];
+                let mut values = HashMap::new();
+                for (number, size) in fields {
+                    let mut value = [0; 8];
+                    value[..*size as usize].copy_from_slice(&bytes[i..i + *size as usize]);
+                    values.insert(*number, u64::from_le_bytes(value));
+                    i += *size as usize;
+                }
+                messages.push((*global, values));
+            }
+        }
+        messages
+    }
+
+    fn of_type(messages: &[Message], global: u16) -> Vec<&HashMap<u8, u64>> {
+        messages
+            .iter()
+            .filter(|(g, _)| *g == global)
+            .map(|(_, fields)| fields)
+            .collect()
+    }
+
+    #[test]
+    fn crc_check_value() {
+        assert_eq!(crc(b"123456789"), 0xBB3D);
+    }
+
+    #[test]
+    fn encode_activity() {
+        let messages = decode(&encode(&steady_summary(900, 10.), 1.));
+        let start = 1656202584 - FIT_EPOCH_OFFSET;
+        assert_eq!(messages[0].0, FILE_ID);
+        assert_eq!(messages[0].1[&0], FILE_TYPE_ACTIVITY);
+
+        let records = of_type(&messages, RECORD);
+        assert_eq!(records.len(), 900);
+        assert_eq!(records[0][&253], start + 1);
+        assert_eq!(records[359][&5], 160934); // one mile in cm
+        assert_eq!(records[359][&6], 4470); // 10 mph in mm/s
+        assert_eq!(records[899][&33], 180);
+
+        let laps = of_type(&messages, LAP);
+        assert_eq!(laps.len(), 3);
+        assert_eq!(
+            laps.iter().map(|l| l[&7]).collect::<Vec<_>>(),
+            vec![360_000, 360_000, 180_000]
+        );
+        assert_eq!(laps[1][&2], start + 360);
+        assert_eq!(laps[0][&24], LAP_TRIGGER_DISTANCE);
+        assert_eq!(laps[2][&24], LAP_TRIGGER_MANUAL);
+        assert_eq!(laps[2][&39], SUB_SPORT_TREADMILL);
+
+        let sessions = of_type(&messages, SESSION);
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0][&2], start);
+        assert_eq!(sessions[0][&7], 900_000);
+        assert_eq!(sessions[0][&9], 402336); // 2.5 miles in cm
+        assert_eq!(sessions[0][&11], 180);
+        assert_eq!(sessions[0][&5], SPORT_RUNNING);
+        assert_eq!(sessions[0][&6], SUB_SPORT_TREADMILL);
+        assert_eq!(sessions[0][&26], 3);
+
+        let activities = of_type(&messages, ACTIVITY);
+        assert_eq!(activities.len(), 1);
+        assert_eq!(activities[0][&1], 1);
+        assert_eq!(messages.last().unwrap().0, ACTIVITY);
+    }
+}
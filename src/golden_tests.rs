@@ -0,0 +1,182 @@
+// every <name>.archive in fixtures/golden is summarized and compared to the
+// expected <name>.json next to it; run with BLESS=1 to write the current
+// output as the new expectation, then review the diff before committing
+use crate::{
+    archive::Archive,
+    athlete::{default_record_distances, Athlete, DEFAULT_WEIGHT},
+    run::{Summary, SummaryOptions},
+};
+use rocket::serde::json::{self, serde_json::Value};
+use std::{env, fs, path::PathBuf};
+
+// relative, so that totals and per-second values are held to the same precision
+const TOLERANCE: f64 = 1e-4;
+
+fn fixture_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("fixtures/golden")
+}
+
+// the archived parameters, as the run was summarized at finalize
+fn summarize(name: &str, archive: &Archive) -> Value {
+    let mut options = SummaryOptions::default();
+    if let Some(treadmill) = &archive.treadmill {
+        options.ticks_per_mile = treadmill.ticks_per_mile;
+        options.debounce_time = treadmill.debounce_time;
+    }
+    let athlete = Athlete {
+        id: "golden".to_string(),
+        name: "golden".to_string(),
+        weight: archive
+            .athlete
+            .as_ref()
+            .map_or(DEFAULT_WEIGHT, |a| a.weight),
+        units: Default::default(),
+        record_distances: default_record_distances(),
+    };
+    let raw_data = archive.raw_data().expect("fixture ticks are valid");
+    match Summary::with_options(name, &athlete, raw_data, &options) {
+        // through text rather than json!, which would print f32s widened to f64
+        Ok(summary) => json::from_str(&format!(
+            "{{\"summary\": {}, \"series\": {}}}",
+            json::to_string(&summary).expect("summaries serialize"),
+            json::to_string(&summary.interval_data).expect("series serialize")
+        ))
+        .expect("summaries are json"),
+        Err(e) => json::json!({ "invalid": format!("{:?}", e) }),
+    }
+}
+
+// one series datum per line keeps goldens reviewable
+fn format_golden(value: &Value) -> String {
+    let mut res = String::from("{\n");
+    let fields: Vec<String> = value
+        .as_object()
+        .expect("goldens are objects")
+        .iter()
+        .map(|(key, value)| match value {
+            Value::Array(items) => {
+                let items: Vec<String> = items.iter().map(|i| format!("    {}", i)).collect();
+                format!("  {:?}: [\n{}\n  ]", key, items.join(",\n"))
+            }
+            _ => {
+                let pretty = json::to_pretty_string(value).expect("values serialize");
+                format!("  {:?}: {}", key, pretty.replace('\n', "\n  "))
+            }
+        })
+        .collect();
+    res.push_str(&fields.join(",\n"));
+    res.push_str("\n}\n");
+    res
+}
+
+fn diff(path: &str, expected: &Value, actual: &Value, res: &mut Vec<String>) {
+    match (expected, actual) {
+        (Value::Number(e), Value::Number(a)) => {
+            let (e, a) = (e.as_f64().unwrap(), a.as_f64().unwrap());
+            if (e - a).abs() > TOLERANCE * f64::max(1., f64::max(e.abs(), a.abs())) {
+                res.push(format!("{}: expected {}, got {}", path, e, a));
+            }
+        }
+        (Value::Object(e), Value::Object(a)) => {
+            let mut keys: Vec<&String> = e.keys().chain(a.keys()).collect();
+            keys.sort();
+            keys.dedup();
+            for key in keys {
+                let path = format!("{}.{}", path, key);
+                match (e.get(key), a.get(key)) {
+                    (Some(e), Some(a)) => diff(&path, e, a, res),
+                    (Some(_), None) => res.push(format!("{}: missing", path)),
+                    (None, Some(_)) => res.push(format!("{}: unexpected", path)),
+                    (None, None) => (),
+                }
+            }
+        }
+        (Value::Array(e), Value::Array(a)) => {
+            if e.len() != a.len() {
+                res.push(format!(
+                    "{}: expected {} items, got {}",
+                    path,
+                    e.len(),
+                    a.len()
+                ));
+            }
+            for (i, (e, a)) in e.iter().zip(a).enumerate() {
+                diff(&format!("{}[{}]", path, i), e, a, res);
+            }
+        }
+        (e, a) if e != a => res.push(format!("{}: expected {}, got {}", path, e, a)),
+        _ => (),
+    }
+}
+
+#[test]
+fn golden_summaries() {
+    let bless = env::var("BLESS").is_ok_and(|v| v != "0");
+    let mut archives: Vec<PathBuf> = fs::read_dir(fixture_dir())
+        .expect("fixtures/golden exists")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|e| e == "archive"))
+        .collect();
+    archives.sort();
+    assert!(!archives.is_empty(), "no golden fixtures");
+
+    let mut failures = vec![];
+    for path in archives {
+        let name = path.file_stem().unwrap().to_string_lossy().to_string();
+        let archive = Archive::decode(&name, &fs::read(&path).unwrap()).expect("valid fixture");
+        let actual = summarize(&name, &archive);
+        let golden = path.with_extension("json");
+        if bless {
+            fs::write(&golden, format_golden(&actual)).unwrap();
+            continue;
+        }
+        let expected: Value = match fs::read_to_string(&golden) {
+            Ok(text) => json::from_str(&text).expect("golden is json"),
+            Err(_) => {
+                failures.push(format!("{}: no golden, run with BLESS=1", name));
+                continue;
+            }
+        };
+        let mut differences = vec![];
+        diff(&name, &expected, &actual, &mut differences);
+        // one broken algorithm would otherwise print every second of every run
+        let count = differences.len();
+        failures.extend(differences.into_iter().take(10));
+        if count > 10 {
+            failures.push(format!("{}: {} more differences", name, count - 10));
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "summaries differ from goldens, rerun with BLESS=1 if intended:\n{}",
+        failures.join("\n")
+    );
+}
+
+#[test]
+fn golden_diff_tolerates_float_noise() {
+    let expected = json::json!({ "a": 1.0, "b": [0.5, 2], "c": "x" });
+    let mut res = vec![];
+    diff(
+        "t",
+        &expected,
+        &json::json!({ "a": 1.00001, "b": [0.5, 2], "c": "x" }),
+        &mut res,
+    );
+    assert!(res.is_empty());
+    diff(
+        "t",
+        &expected,
+        &json::json!({ "a": 1.01, "b": [0.5], "d": "x" }),
+        &mut res,
+    );
+    assert_eq!(
+        res,
+        vec![
+            "t.a: expected 1, got 1.01",
+            "t.b: expected 2 items, got 1",
+            "t.c: missing",
+            "t.d: unexpected"
+        ]
+    );
+}
@@ -0,0 +1,471 @@
+use roxmltree::{Document, Node};
+use std::time::UNIX_EPOCH;
+use uuid::Uuid;
+
+use crate::{
+    archive::{Archive, IngestionMetadata},
+    athlete::{Athlete, Units},
+    aws::{push_summary_to_db, summary_exists, update_personal_records, write_data_to_s3},
+    config,
+    constants::{MAX_IMPORT_DISTANCE, MAX_IMPORT_DURATION, MAX_IMPORT_TICKS, METERS_PER_MILE},
+    error::{ApiError, ErrorCode},
+    export::{parse_iso8601, GPX_RUN_EXTENSION},
+    run::{RawData, Summary, Tickstamp},
+};
+
+const EARTH_RADIUS_MILES: f64 = 3958.8;
+
+#[derive(Debug)]
+pub struct ImportError {
+    pub msg: String,
+}
+
+fn import_error(msg: impl Into<String>) -> ImportError {
+    ImportError { msg: msg.into() }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ImportFormat {
+    Tcx,
+    Gpx,
+    Csv,
+}
+
+impl ImportFormat {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ImportFormat::Tcx => "tcx",
+            ImportFormat::Gpx => "gpx",
+            ImportFormat::Csv => "csv",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ImportFormat> {
+        match name.to_ascii_lowercase().as_str() {
+            "tcx" => Some(ImportFormat::Tcx),
+            "gpx" => Some(ImportFormat::Gpx),
+            "csv" => Some(ImportFormat::Csv),
+            _ => None,
+        }
+    }
+}
+
+// how far a run had got at each point, as read from an imported file
+#[derive(Debug, PartialEq)]
+pub struct DistanceSeries {
+    pub start_time: u64,         // epoch millis
+    pub points: Vec<(u64, f32)>, // millis since start, miles since start
+}
+
+impl DistanceSeries {
+    // start_time is only needed for csv files, the xml formats carry their own
+    pub fn parse(
+        format: ImportFormat,
+        text: &str,
+        start_time: Option<u64>,
+    ) -> Result<DistanceSeries, ImportError> {
+        let (start_time, mut points) = match format {
+            ImportFormat::Tcx => parse_tcx(text)?,
+            ImportFormat::Gpx => parse_gpx(text)?,
+            ImportFormat::Csv => {
+                let start_time = start_time
+                    .ok_or_else(|| import_error("csv imports need the run's start time"))?;
+                (start_time, parse_csv(text)?)
+            }
+        };
+        if points.is_empty() {
+            return Err(import_error("file has no points with a distance"));
+        }
+        points.sort_by_key(|p| p.0);
+        check_points(&points)?;
+        Ok(DistanceSeries { start_time, points })
+    }
+
+    // a tick for every tick's worth of distance, at the time the series reaches it,
+    // interpolating linearly between points; the first tick marks the start
+    pub fn ticks(&self, ticks_per_mile: f32) -> Result<Vec<Tickstamp>, ImportError> {
+        let distance = self.points.last().map_or(0., |p| p.1);
+        let count = distance * ticks_per_mile;
+        if !count.is_finite() || count > MAX_IMPORT_TICKS as f32 {
+            return Err(import_error(format!(
+                "run would take more than {} ticks",
+                MAX_IMPORT_TICKS
+            )));
+        }
+        let mut ticks: Vec<Tickstamp> = vec![0];
+        let mut prev = (0, 0.);
+        let mut next_tick = 1;
+        for &(time, distance) in &self.points {
+            let (prev_time, prev_distance) = prev;
+            let tick_distance = |k: u32| k as f32 / ticks_per_mile;
+            while distance > prev_distance && tick_distance(next_tick) <= distance {
+                let part = (tick_distance(next_tick) - prev_distance) as f64
+                    / (distance - prev_distance) as f64;
+                ticks
+                    .push(prev_time as Tickstamp + ((time - prev_time) as f64 * part) as Tickstamp);
+                next_tick += 1;
+            }
+            if distance >= prev_distance {
+                prev = (time, distance);
+            }
+        }
+        Ok(ticks)
+    }
+
+    pub fn raw_data(&self, ticks_per_mile: f32) -> Result<RawData, ImportError> {
+        Ok(RawData {
+            start_time: self.start_time.to_string(),
+            tickstamps: self.ticks(ticks_per_mile)?,
+            // the file's times are wall-clock times already
+            clock_samples: vec![],
+            discontinuities: vec![],
+        })
+    }
+}
+
+// files come from anywhere: distances must be real and never shrink, and a run is
+// capped so that it can't be turned into endless ticks
+fn check_points(points: &[(u64, f32)]) -> Result<(), ImportError> {
+    let mut prev = 0.;
+    for &(time, distance) in points {
+        if !distance.is_finite() || distance < 0. {
+            return Err(import_error(format!("invalid distance: {}", distance)));
+        }
+        if distance < prev {
+            return Err(import_error(format!("distance decreases at {} ms", time)));
+        }
+        if time > MAX_IMPORT_DURATION {
+            return Err(import_error(format!(
+                "run is longer than {} hours",
+                MAX_IMPORT_DURATION / 3_600_000
+            )));
+        }
+        prev = distance;
+    }
+    if prev > MAX_IMPORT_DISTANCE {
+        return Err(import_error(format!(
+            "run is longer than {} miles",
+            MAX_IMPORT_DISTANCE
+        )));
+    }
+    Ok(())
+}
+
+fn child<'a, 'i>(node: Node<'a, 'i>, name: &str) -> Option<Node<'a, 'i>> {
+    node.children().find(|n| n.tag_name().name() == name)
+}
+
+fn child_text<'a>(node: Node<'a, '_>, name: &str) -> Option<&'a str> {
+    child(node, name)?.text().map(|t| t.trim())
+}
+
+fn time(node: Node, name: &str) -> Result<u64, ImportError> {
+    let value = child_text(node, name)
+        .ok_or_else(|| import_error(format!("<{}> has no <{}>", node.tag_name().name(), name)))?;
+    parse_iso8601(value).ok_or_else(|| import_error(format!("invalid time: {:?}", value)))
+}
+
+fn relative(points: Vec<(u64, f32)>, start_time: u64) -> Vec<(u64, f32)> {
+    points
+        .into_iter()
+        .map(|(time, distance)| (time.saturating_sub(start_time), distance))
+        .collect()
+}
+
+fn parse_xml(text: &str) -> Result<Document<'_>, ImportError> {
+    Document::parse(text).map_err(|e| import_error(format!("invalid xml: {}", e)))
+}
+
+// trackpoints without a distance (e.g. heart rate only) are skipped
+fn parse_tcx(text: &str) -> Result<(u64, Vec<(u64, f32)>), ImportError> {
+    let doc = parse_xml(text)?;
+    let mut points = vec![];
+    for trackpoint in doc.descendants().filter(|n| n.has_tag_name("Trackpoint")) {
+        let distance = match child_text(trackpoint, "DistanceMeters") {
+            Some(d) => d
+                .parse::<f32>()
+                .map_err(|_| import_error(format!("invalid distance: {:?}", d)))?,
+            None => continue,
+        };
+        points.push((time(trackpoint, "Time")?, distance / METERS_PER_MILE));
+    }
+    let start_time = match doc.descendants().find(|n| n.has_tag_name("Activity")) {
+        Some(activity) if child(activity, "Id").is_some() => time(activity, "Id")?,
+        _ => points.iter().map(|p| p.0).min().unwrap_or_default(),
+    };
+    Ok((start_time, relative(points, start_time)))
+}
+
+fn haversine(a: (f64, f64), b: (f64, f64)) -> f64 {
+    let (lat1, lon1) = (a.0.to_radians(), a.1.to_radians());
+    let (lat2, lon2) = (b.0.to_radians(), b.1.to_radians());
+    let h = ((lat2 - lat1) / 2.).sin().powi(2)
+        + lat1.cos() * lat2.cos() * ((lon2 - lon1) / 2.).sin().powi(2);
+    2. * EARTH_RADIUS_MILES * h.sqrt().asin()
+}
+
+// distance comes from our own extension when present, otherwise from the positions
+fn parse_gpx(text: &str) -> Result<(u64, Vec<(u64, f32)>), ImportError> {
+    let doc = parse_xml(text)?;
+    let mut points = vec![];
+    let mut travelled = 0.;
+    let mut prev_position = None;
+    for trkpt in doc.descendants().filter(|n| n.has_tag_name("trkpt")) {
+        let extension_distance = trkpt
+            .descendants()
+            .find(|n| {
+                n.tag_name().name() == "distance"
+                    && n.tag_name().namespace() == Some(GPX_RUN_EXTENSION)
+            })
+            .and_then(|n| n.text());
+        let distance = match extension_distance {
+            Some(d) => {
+                d.trim()
+                    .parse::<f32>()
+                    .map_err(|_| import_error(format!("invalid distance: {:?}", d)))?
+                    / METERS_PER_MILE
+            }
+            None => {
+                let coordinate = |name| {
+                    trkpt
+                        .attribute(name)
+                        .and_then(|c: &str| c.parse::<f64>().ok())
+                        .ok_or_else(|| import_error(format!("trkpt has no valid {}", name)))
+                };
+                let position = (coordinate("lat")?, coordinate("lon")?);
+                if let Some(prev) = prev_position {
+                    travelled += haversine(prev, position);
+                }
+                prev_position = Some(position);
+                travelled as f32
+            }
+        };
+        points.push((time(trkpt, "time")?, distance));
+    }
+    let start_time = match doc.descendants().find(|n| n.has_tag_name("metadata")) {
+        Some(metadata) if child(metadata, "time").is_some() => time(metadata, "time")?,
+        _ => points.iter().map(|p| p.0).min().unwrap_or_default(),
+    };
+    Ok((start_time, relative(points, start_time)))
+}
+
+// reads the series csv we export: a timeSeconds and a distanceMi or distanceKm column
+fn parse_csv(text: &str) -> Result<Vec<(u64, f32)>, ImportError> {
+    let mut lines = text.lines().filter(|l| !l.trim().is_empty());
+    let header: Vec<&str> = lines
+        .next()
+        .ok_or_else(|| import_error("csv file is empty"))?
+        .split(',')
+        .map(|c| c.trim())
+        .collect();
+    let column = |name: &str| header.iter().position(|c| *c == name);
+    let time_column =
+        column("timeSeconds").ok_or_else(|| import_error("csv has no timeSeconds column"))?;
+    let (distance_column, units) = match (column("distanceMi"), column("distanceKm")) {
+        (Some(c), _) => (c, Units::Imperial),
+        (None, Some(c)) => (c, Units::Metric),
+        (None, None) => return Err(import_error("csv has no distanceMi or distanceKm column")),
+    };
+
+    let mut points = vec![];
+    for (i, line) in lines.enumerate() {
+        let fields: Vec<&str> = line.split(',').map(|f| f.trim()).collect();
+        let field = |column: usize| {
+            fields
+                .get(column)
+                .and_then(|f| f.parse::<f32>().ok())
+                .ok_or_else(|| import_error(format!("invalid value in row {}", i + 2)))
+        };
+        let time = field(time_column)?;
+        if !time.is_finite() || time < 0. {
+            return Err(import_error(format!("invalid time in row {}", i + 2)));
+        }
+        let distance = field(distance_column)?;
+        points.push(((time * 1000.) as u64, distance / units.convert(1.)));
+    }
+    Ok(points)
+}
+
+// the same file imported twice gets the same id, so history can be re-imported safely
+pub fn run_id(athlete_id: &str, start_time: u64) -> String {
+    let name = format!("{}-{}", athlete_id, start_time);
+    format!("{}", Uuid::new_v5(&Uuid::NAMESPACE_OID, name.as_bytes()))
+}
+
+// stores an imported run like a finished one: raw archive, personal records, then
+// the summary, whose presence marks the import done; a retry after a failed summary
+// write folds the run into records that hold it already, which leaves them and its
+// new personal bests as they were
+pub async fn store<'a>(
+    run_id: &'a str,
+    athlete: &Athlete,
+    format: ImportFormat,
+    series: &DistanceSeries,
+) -> Result<Summary<'a>, ApiError> {
+    if summary_exists(run_id).await? {
+        return Err(ApiError::new(
+            ErrorCode::RunFinalized,
+            "run was already imported",
+        ));
+    }
+    let options = &config::get().analysis;
+    let raw_data = series.raw_data(options.ticks_per_mile)?;
+    let ingestion = IngestionMetadata {
+        finalized_at: std::time::SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("bad time")
+            .as_millis() as u64,
+        server_version: env!("CARGO_PKG_VERSION").to_string(),
+        dropped_ticks: 0,
+        imported_from: Some(format.name().to_string()),
+    };
+    let data = Archive::new(run_id, &raw_data, athlete, options, ingestion).encode()?;
+    let mut summary = Summary::with_options(run_id, athlete, raw_data, options)?;
+
+    write_data_to_s3(run_id, data).await?;
+    summary.new_personal_bests = update_personal_records(&athlete.id, &summary).await?;
+    push_summary_to_db(&summary).await?;
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        constants::TICKS_PER_MILE,
+        export,
+        test_fixtures::{athlete, steady_summary, START_TIME},
+    };
+
+    fn assert_imported(series: &DistanceSeries) {
+        assert_eq!(series.start_time, START_TIME);
+        let summary = Summary::new(
+            "imported",
+            &athlete(),
+            series.raw_data(TICKS_PER_MILE).unwrap(),
+        )
+        .unwrap();
+        assert!((summary.total_time_secs as i32 - 300).abs() <= 1);
+        assert!((summary.total_distance - 0.5).abs() < 0.005);
+        let speed = summary.interval_data[150].speed;
+        assert!((speed - 6.).abs() < 0.1, "speed {}", speed);
+        let lap = summary.distance_records.0["lap"].as_ref().unwrap();
+        assert!((lap.time as i32 - 150_000).abs() <= 2000);
+    }
+
+    #[test]
+    fn format_names() {
+        assert_eq!(ImportFormat::from_name("TCX"), Some(ImportFormat::Tcx));
+        assert_eq!(ImportFormat::from_name("fit"), None);
+    }
+
+    #[test]
+    fn synthesized_ticks() {
+        let series = DistanceSeries {
+            start_time: 0,
+            points: vec![
+                (1000, 0.),
+                (2000, 2. / TICKS_PER_MILE),
+                (3000, 2. / TICKS_PER_MILE),
+                (4000, 3. / TICKS_PER_MILE),
+            ],
+        };
+        assert_eq!(
+            series.ticks(TICKS_PER_MILE).unwrap(),
+            vec![0, 1500, 2000, 4000]
+        );
+    }
+
+    #[test]
+    fn import_tcx() {
+        let tcx = export::tcx(&steady_summary(300, 6.), 1.);
+        assert_imported(&DistanceSeries::parse(ImportFormat::Tcx, &tcx, None).unwrap());
+    }
+
+    #[test]
+    fn import_gpx() {
+        let gpx = export::gpx(&steady_summary(300, 6.));
+        assert_imported(&DistanceSeries::parse(ImportFormat::Gpx, &gpx, None).unwrap());
+    }
+
+    #[test]
+    fn import_gpx_positions() {
+        let gpx = r#"<gpx version="1.1" xmlns="http://www.topografix.com/GPX/1/1"><trk><trkseg>
+            <trkpt lat="0" lon="0"><time>2022-06-26T00:16:24Z</time></trkpt>
+            <trkpt lat="0" lon="0.01"><time>2022-06-26T00:17:24Z</time></trkpt>
+            </trkseg></trk></gpx>"#;
+        let series = DistanceSeries::parse(ImportFormat::Gpx, gpx, None).unwrap();
+        assert_eq!(series.start_time, 1656202584000);
+        assert_eq!(series.points[1].0, 60000);
+        assert!((series.points[1].1 - 0.691).abs() < 0.001);
+    }
+
+    #[test]
+    fn import_csv() {
+        let summary = steady_summary(300, 6.);
+        let mut csv = crate::csv::series_header(Units::Metric);
+        for datum in &summary.interval_data {
+            csv.push_str(&crate::csv::series_row(datum, Units::Metric));
+        }
+        assert!(DistanceSeries::parse(ImportFormat::Csv, &csv, None).is_err());
+        let series = DistanceSeries::parse(ImportFormat::Csv, &csv, Some(START_TIME)).unwrap();
+        assert_imported(&series);
+    }
+
+    #[test]
+    fn import_fail() {
+        assert!(DistanceSeries::parse(ImportFormat::Tcx, "<nope", None).is_err());
+        assert!(
+            DistanceSeries::parse(ImportFormat::Tcx, "<TrainingCenterDatabase/>", None).is_err()
+        );
+        assert!(DistanceSeries::parse(ImportFormat::Csv, "time,distance\n1,2", Some(0)).is_err());
+    }
+
+    fn csv(rows: &str) -> Result<DistanceSeries, ImportError> {
+        let text = format!("timeSeconds,distanceMi\n{}", rows);
+        DistanceSeries::parse(ImportFormat::Csv, &text, Some(START_TIME))
+    }
+
+    #[test]
+    fn import_rejects_non_finite_values() {
+        for distance in ["inf", "NaN", "-inf"] {
+            let tcx = format!(
+                "<TrainingCenterDatabase><Trackpoint><Time>2022-06-26T00:16:24Z</Time>\
+                 <DistanceMeters>{}</DistanceMeters></Trackpoint></TrainingCenterDatabase>",
+                distance
+            );
+            assert!(DistanceSeries::parse(ImportFormat::Tcx, &tcx, None).is_err());
+            assert!(csv(&format!("0,0\n1,{}", distance)).is_err());
+            assert!(csv(&format!("{},0.01", distance)).is_err());
+        }
+    }
+
+    #[test]
+    fn import_rejects_negative_values() {
+        assert!(csv("0,0\n1,-0.01").is_err());
+        assert!(csv("-1,0\n1,0.01").is_err());
+    }
+
+    #[test]
+    fn import_rejects_decreasing_distance() {
+        assert!(csv("0,0\n1,0.02\n2,0.01").is_err());
+        assert!(csv("0,0\n1,0.01\n2,0.01").is_ok());
+    }
+
+    #[test]
+    fn import_caps_run_size() {
+        assert!(csv("0,0\n3600,100").is_ok());
+        assert!(csv("0,0\n3600,100.5").is_err());
+        assert!(csv("0,0\n86400,1").is_ok());
+        assert!(csv("0,0\n86401,1").is_err());
+        let series = csv("0,0\n3600,10").unwrap();
+        assert!(series.ticks(TICKS_PER_MILE).is_ok());
+        assert!(series.ticks(MAX_IMPORT_TICKS as f32).is_err());
+    }
+
+    #[test]
+    fn run_ids_are_stable() {
+        assert_eq!(run_id("athlete", 1), run_id("athlete", 1));
+        assert_ne!(run_id("athlete", 1), run_id("athlete", 2));
+    }
+}
@@ -1,23 +1,39 @@
 
-use crate::run::Summary;
+use crate::{athlete::Athlete, auth::API_KEY_HEADER, records::RecordWindow, run::Summary};
 
 #[cfg(test)]
 use super::*;
+use dotenv::dotenv;
 use regex::Regex;
-use rocket::{local::blocking::Client, serde::json};
-use dotenv;
+use rocket::{http::Header, local::blocking::Client, serde::json};
+use std::{collections::HashMap, env};
 
 #[ignore]
 #[test]
 fn push_data_and_finalize() {
     dotenv().ok();
-    let rocket = rocket::build().mount(
-        "/",
-        routes![routes::post_data, routes::new_run, routes::finalize_run],
-    );
-    let client = Client::tracked(rocket).expect("valid rocket instance");
+    // the server as main launches it, configuration, routes and catchers alike
+    let client = Client::tracked(crate::rocket()).expect("valid rocket instance");
+    // the plain admin key whose hash is configured as ADMIN_API_KEY_HASH
+    let admin_key = Header::new(API_KEY_HEADER, env::var("TEST_ADMIN_API_KEY").unwrap());
+
+    let response = client
+        .post("/athlete")
+        .header(admin_key.clone())
+        .body("{\"name\": \"Integration Test\"}")
+        .dispatch();
+    let athlete: Athlete = json::from_str(&response.into_string().unwrap()).unwrap();
+
+    let response = client
+        .post("/device-key?name=integration-test")
+        .header(admin_key)
+        .dispatch();
+    let device_key = Header::new(API_KEY_HEADER, response.into_string().unwrap());
 
-    let response = client.get("/new-run").dispatch();
+    let response = client
+        .get(format!("/new-run?athlete_id={}", athlete.id))
+        .header(device_key.clone())
+        .dispatch();
 
     let re = Regex::new(r"[0-9a-f]{8}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{12}").unwrap();
     let run_id = response.into_string().unwrap();
@@ -34,6 +50,7 @@ fn push_data_and_finalize() {
         let data = ms.join(",");
         client
             .post(format!("/run/{}", run_id))
+            .header(device_key.clone())
             .body(data)
             .dispatch();
     });
@@ -42,12 +59,24 @@ fn push_data_and_finalize() {
         req_count * ticks_per_req
     );
 
-    let response = client.post(format!("/run/{}/finish", run_id)).dispatch();
+    let response = client
+        .post(format!("/run/{}/finish", run_id))
+        .header(device_key)
+        .dispatch();
     let summary_response = response.into_string().unwrap();
     let actual_summary: Summary = json::from_str(&summary_response).unwrap();
-    let expected_summary_str = "{\"startTime\":\"1656202584971\",\"bestDistances\":{\"fiveMiles\":null,\"halfMile\":{\"left\":1,\"right\":154,\"leftD\":0.0032499998,\"rightD\":0.5055227,\"time\":153},\"twoMiles\":null,\"fiveKm\":null,\"oneMile\":null,\"lap\":{\"left\":1,\"right\":78,\"leftD\":0.0032499998,\"rightD\":0.2559621,\"time\":77},\"tenKm\":null,\"threeMiles\":null,\"oneKm\":null,\"fourMiles\":null},\"totalTime\":299,\"maxRectangle\":{\"start\":24,\"end\":299,\"height\":11.818182,\"area\":3250.0},\"runId\":\"10ef491c-426c-406c-a885-15fbf1e0e9e0\",\"totalCalories\":151.2582,\"totalDistance\":0.98149997}";
+    let expected_summary_str = "{\"startTime\":\"1656202584971\",\"bestDistances\":{\"fiveMiles\":null,\"halfMile\":{\"left\":1000,\"right\":154000,\"leftD\":0.0032499998,\"rightD\":0.5055227,\"time\":153000},\"twoMiles\":null,\"fiveKm\":null,\"oneMile\":null,\"lap\":{\"left\":1000,\"right\":78000,\"leftD\":0.0032499998,\"rightD\":0.2559621,\"time\":77000},\"tenKm\":null,\"threeMiles\":null,\"oneKm\":null,\"fourMiles\":null},\"totalTime\":299,\"maxRectangle\":{\"start\":24000,\"end\":299000,\"height\":11.818182,\"area\":3250.0},\"runId\":\"10ef491c-426c-406c-a885-15fbf1e0e9e0\",\"totalCalories\":151.2582,\"totalDistance\":0.98149997}";
 
-    let expected_summary: Summary = json::from_str(expected_summary_str).unwrap();
+    let mut expected_summary: Summary = json::from_str(expected_summary_str).unwrap();
+    // ids and the start time are new on every run
+    expected_summary.start_time = actual_summary.start_time.clone();
+    expected_summary.id = &run_id;
+    expected_summary.athlete_id = athlete.id;
+    // a new athlete's first run is their best in every window for each record it covers
+    expected_summary.new_personal_bests = HashMap::from([
+        ("lap".to_string(), RecordWindow::ALL.to_vec()),
+        ("halfMile".to_string(), RecordWindow::ALL.to_vec()),
+    ]);
     assert_eq!(expected_summary, actual_summary);
 
     assert_eq!(cache::fullzrange(&run_id).unwrap().len(), 0);
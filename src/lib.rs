@@ -0,0 +1,70 @@
+#[cfg(test)] mod integration_tests;
+#[cfg(test)] mod golden_tests;
+#[cfg(test)] mod test_fixtures;
+
+pub mod archive;
+pub mod athlete;
+pub mod auth;
+pub mod aws;
+pub mod cache;
+pub mod cli;
+pub mod clock;
+pub mod config;
+pub mod constants;
+pub mod csv;
+pub mod device;
+pub mod error;
+pub mod export;
+pub mod filter;
+pub mod fit;
+pub mod import;
+pub mod records;
+pub mod replay;
+pub mod resummarize;
+pub mod routes;
+pub mod run;
+pub mod simulate;
+pub mod timeline;
+
+use rocket::{fairing::AdHoc, Build, Rocket};
+
+#[macro_use]
+extern crate rocket;
+
+pub fn rocket() -> Rocket<Build> {
+    rocket::custom(config::figment())
+        // a server that can't reach its tables or bucket shouldn't start at all
+        .attach(AdHoc::try_on_ignite("Configuration", |rocket| async {
+            match config::init() {
+                Ok(_) => Ok(rocket),
+                Err(e) => {
+                    error!("{}", e.msg);
+                    Err(rocket)
+                }
+            }
+        }))
+        .mount(
+            "/",
+            routes![
+                routes::post_data,
+                routes::new_run,
+                routes::finalize_run,
+                routes::new_athlete,
+                routes::get_athlete,
+                routes::list_runs,
+                routes::new_device_key,
+                routes::new_user_key,
+                routes::resummarize_run,
+                routes::resummarize_all,
+                routes::get_raw,
+                routes::get_series,
+                routes::get_tcx,
+                routes::get_gpx,
+                routes::get_fit,
+                routes::list_runs_csv,
+                routes::get_series_csv,
+                routes::import_run
+            ],
+        )
+        .register("/", catchers![error::default_catcher])
+}
@@ -1,26 +1,23 @@
-#[cfg(test)] mod integration_tests;
-
-mod aws;
-mod cache;
-mod constants;
-mod routes;
-mod run;
-
 use dotenv::dotenv;
+use rusty_dusty::{cli, config, rocket};
+use std::{env, process};
 
-#[macro_use]
-extern crate rocket;
-
-#[launch]
-fn launch() -> _ {
+#[rocket::main]
+async fn main() {
     dotenv().ok();
-    rocket::build()
-    .mount(
-        "/",
-        routes![
-            routes::post_data,
-            routes::new_run,
-            routes::finalize_run
-        ],
-    )
-}
\ No newline at end of file
+    if let Err(e) = config::init() {
+        eprintln!("{}", e.msg);
+        process::exit(1);
+    }
+    let args: Vec<String> = env::args().collect();
+    let res = match args.get(1).map(|a| a.as_str()) {
+        Some("resummarize") => cli::resummarize(&args[2..]).await,
+        Some("import") => cli::import(&args[2..]).await,
+        Some("golden") => cli::golden(&args[2..]).await,
+        _ => rocket().launch().await.map(|_| ()).map_err(|e| e.to_string()),
+    };
+    if let Err(e) = res {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
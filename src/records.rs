@@ -0,0 +1,281 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::run::{Summary, Timestamp};
+
+const MILLIS_PER_DAY: u64 = 24 * 60 * 60 * 1000;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum RecordWindow {
+    #[serde(rename = "allTime")]
+    AllTime,
+    #[serde(rename = "last30Days")]
+    Last30Days,
+    #[serde(rename = "last90Days")]
+    Last90Days,
+    #[serde(rename = "last365Days")]
+    Last365Days,
+}
+
+impl RecordWindow {
+    pub const ALL: [RecordWindow; 4] = [
+        RecordWindow::AllTime,
+        RecordWindow::Last30Days,
+        RecordWindow::Last90Days,
+        RecordWindow::Last365Days,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            RecordWindow::AllTime => "allTime",
+            RecordWindow::Last30Days => "last30Days",
+            RecordWindow::Last90Days => "last90Days",
+            RecordWindow::Last365Days => "last365Days",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<RecordWindow> {
+        RecordWindow::ALL.into_iter().find(|w| w.name() == name)
+    }
+
+    fn days(&self) -> Option<u64> {
+        match self {
+            RecordWindow::AllTime => None,
+            RecordWindow::Last30Days => Some(30),
+            RecordWindow::Last90Days => Some(90),
+            RecordWindow::Last365Days => Some(365),
+        }
+    }
+
+    fn cutoff(&self, now: u64) -> u64 {
+        match self.days() {
+            Some(days) => now.saturating_sub(days * MILLIS_PER_DAY),
+            None => 0,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, PartialEq, Clone)]
+pub struct PersonalBest {
+    #[serde(rename = "runId")]
+    pub run_id: String,
+    #[serde(rename = "runStart")]
+    pub run_start: u64, // epoch millis when the run started
+    #[serde(rename = "time")]
+    pub time: Timestamp,
+}
+
+#[derive(Debug, PartialEq, Default)]
+pub struct RecordHistory {
+    pub all_time: Option<PersonalBest>,
+    // bests from the last year, oldest first; each is faster than every later entry,
+    // so the best within a window is the first entry that falls inside it
+    pub recent: Vec<PersonalBest>,
+}
+
+impl RecordHistory {
+    pub fn best(&self, window: RecordWindow, now: u64) -> Option<&PersonalBest> {
+        match window {
+            RecordWindow::AllTime => self.all_time.as_ref(),
+            _ => {
+                let cutoff = window.cutoff(now);
+                self.recent.iter().find(|pb| pb.run_start >= cutoff)
+            }
+        }
+    }
+
+    // the best in a window as it stood when pb's run started, from other runs up to
+    // then only, since imported runs may be older than ones already in the history
+    fn best_before(&self, window: RecordWindow, pb: &PersonalBest) -> Option<&PersonalBest> {
+        let earlier = |b: &&PersonalBest| b.run_start <= pb.run_start && b.run_id != pb.run_id;
+        match self.all_time.as_ref().filter(earlier) {
+            Some(best) if window == RecordWindow::AllTime => Some(best),
+            _ => {
+                let cutoff = window.cutoff(pb.run_start);
+                self.recent
+                    .iter()
+                    .filter(earlier)
+                    .filter(|b| b.run_start >= cutoff)
+                    .min_by_key(|b| b.time)
+            }
+        }
+    }
+
+    fn add(&mut self, pb: PersonalBest) -> Vec<RecordWindow> {
+        let new_windows: Vec<RecordWindow> = RecordWindow::ALL
+            .iter()
+            .filter(|w| match self.best_before(**w, &pb) {
+                Some(best) => pb.time < best.time,
+                None => true,
+            })
+            .copied()
+            .collect();
+        // folding in the same run again, as a retried import does, changes nothing; a
+        // resummarized run that got slower hands the record to the fastest of the runs
+        // still in recent, if that is faster
+        match &self.all_time {
+            Some(best) if best.run_id == pb.run_id => {
+                self.all_time = self
+                    .recent
+                    .iter()
+                    .filter(|b| b.run_id != pb.run_id)
+                    .chain([&pb])
+                    .min_by_key(|b| b.time)
+                    .cloned();
+            }
+            Some(best) if pb.time >= best.time => (),
+            _ => self.all_time = Some(pb.clone()),
+        }
+
+        self.recent.retain(|b| b.run_id != pb.run_id);
+        self.recent.push(pb);
+        self.recent.sort_by_key(|b| b.run_start);
+        let newest = self.recent.last().map_or(0, |b| b.run_start);
+        let cutoff = RecordWindow::Last365Days.cutoff(newest);
+        let mut kept: Vec<PersonalBest> = vec![];
+        for b in self.recent.drain(..).rev() {
+            if b.run_start >= cutoff && kept.last().is_none_or(|k| b.time < k.time) {
+                kept.push(b);
+            }
+        }
+        kept.reverse();
+        self.recent = kept;
+
+        new_windows
+    }
+}
+
+#[derive(Debug, PartialEq, Default)]
+pub struct PersonalRecords(pub HashMap<String, RecordHistory>);
+
+impl PersonalRecords {
+    // folds a run's distance records into the history, returning the windows
+    // in which each record was a new personal best
+    pub fn update(&mut self, summary: &Summary) -> HashMap<String, Vec<RecordWindow>> {
+        let run_start: u64 = summary.start_time.parse().unwrap_or_default();
+        let mut res = HashMap::new();
+        for (name, record) in &summary.distance_records.0 {
+            let record = match record {
+                Some(r) => r,
+                None => continue,
+            };
+            let new_windows = self
+                .0
+                .entry(name.to_string())
+                .or_default()
+                .add(PersonalBest {
+                    run_id: summary.id.to_string(),
+                    run_start,
+                    time: record.time,
+                });
+            if !new_windows.is_empty() {
+                res.insert(name.to_string(), new_windows);
+            }
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pb(run_id: &str, day: u64, time: Timestamp) -> PersonalBest {
+        PersonalBest {
+            run_id: run_id.to_string(),
+            run_start: day * MILLIS_PER_DAY,
+            time,
+        }
+    }
+
+    #[test]
+    fn first_record_is_best_in_every_window() {
+        let mut history = RecordHistory::default();
+        let new_windows = history.add(pb("a", 400, 300));
+        assert_eq!(new_windows, RecordWindow::ALL.to_vec());
+        assert_eq!(history.all_time, Some(pb("a", 400, 300)));
+    }
+
+    #[test]
+    fn slower_record_only_beats_expired_windows() {
+        let mut history = RecordHistory::default();
+        history.add(pb("a", 400, 300));
+        let new_windows = history.add(pb("b", 460, 320));
+        assert_eq!(new_windows, vec![RecordWindow::Last30Days]);
+        assert_eq!(history.all_time, Some(pb("a", 400, 300)));
+        assert_eq!(
+            history.best(RecordWindow::Last90Days, 460 * MILLIS_PER_DAY),
+            Some(&pb("a", 400, 300))
+        );
+        assert_eq!(
+            history.best(RecordWindow::Last30Days, 460 * MILLIS_PER_DAY),
+            Some(&pb("b", 460, 320))
+        );
+    }
+
+    #[test]
+    fn faster_record_replaces_slower_recent_records() {
+        let mut history = RecordHistory::default();
+        history.add(pb("a", 400, 300));
+        history.add(pb("b", 460, 320));
+        let new_windows = history.add(pb("c", 470, 290));
+        assert_eq!(new_windows, RecordWindow::ALL.to_vec());
+        assert_eq!(history.recent, vec![pb("c", 470, 290)]);
+    }
+
+    #[test]
+    fn imported_older_record_is_judged_as_of_its_run() {
+        let mut history = RecordHistory::default();
+        history.add(pb("b", 460, 290));
+        // slower than b, but b came later
+        let new_windows = history.add(pb("a", 450, 300));
+        assert_eq!(new_windows, RecordWindow::ALL.to_vec());
+        assert_eq!(history.all_time, Some(pb("b", 460, 290)));
+        assert_eq!(history.recent, vec![pb("b", 460, 290)]);
+        // faster than the best before it, which is all that counts
+        let new_windows = history.add(pb("c", 455, 295));
+        assert_eq!(new_windows, RecordWindow::ALL.to_vec());
+        // and only beaten in the windows that had a faster run before it
+        let new_windows = history.add(pb("d", 470, 298));
+        assert_eq!(new_windows, vec![]);
+        let new_windows = history.add(pb("e", 500, 297));
+        assert_eq!(new_windows, vec![RecordWindow::Last30Days]);
+    }
+
+    #[test]
+    fn adding_a_run_again_changes_nothing() {
+        let mut history = RecordHistory::default();
+        history.add(pb("a", 400, 300));
+        let new_windows = history.add(pb("b", 460, 320));
+        let recent = history.recent.clone();
+        assert_eq!(history.add(pb("b", 460, 320)), new_windows);
+        assert_eq!(history.recent, recent);
+        assert_eq!(history.all_time, Some(pb("a", 400, 300)));
+        assert_eq!(history.add(pb("a", 400, 300)), RecordWindow::ALL.to_vec());
+        assert_eq!(history.all_time, Some(pb("a", 400, 300)));
+    }
+
+    #[test]
+    fn slower_resummarized_run_gives_up_its_record() {
+        let mut history = RecordHistory::default();
+        history.add(pb("a", 400, 300));
+        history.add(pb("b", 460, 310));
+        history.add(pb("a", 400, 320));
+        assert_eq!(history.all_time, Some(pb("b", 460, 310)));
+        assert_eq!(history.recent, vec![pb("b", 460, 310)]);
+        // with nothing faster at hand it keeps it at its new time
+        let mut history = RecordHistory::default();
+        history.add(pb("a", 400, 300));
+        history.add(pb("a", 400, 320));
+        assert_eq!(history.all_time, Some(pb("a", 400, 320)));
+    }
+
+    #[test]
+    fn records_older_than_a_year_are_dropped() {
+        let mut history = RecordHistory::default();
+        history.add(pb("a", 0, 300));
+        history.add(pb("b", 400, 320));
+        assert_eq!(history.recent, vec![pb("b", 400, 320)]);
+        assert_eq!(history.all_time, Some(pb("a", 0, 300)));
+    }
+}
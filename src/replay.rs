@@ -0,0 +1,249 @@
+use crate::{
+    run::{DistanceRecordSet, RunListing, Summary, Tickstamp},
+    simulate::Batch,
+};
+
+// the ticks in posts of batch_size, each sent when its last tick happened on
+// the device, in millis since the first tick and on the device clock
+pub fn replay_batches(ticks: &[Tickstamp], batch_size: usize) -> Vec<Batch> {
+    let first = match ticks.first() {
+        Some(t) => *t,
+        None => return vec![],
+    };
+    ticks
+        .chunks(batch_size.max(1))
+        .map(|chunk| {
+            let last = chunk.iter().max().copied().unwrap_or(first);
+            Batch {
+                send_at: last.saturating_sub(first) as u64,
+                device_time: last,
+                ticks: chunk.to_vec(),
+            }
+        })
+        .collect()
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Difference {
+    pub field: String,
+    pub original: String,
+    pub replayed: String,
+}
+
+fn difference(field: &str, original: impl ToString, replayed: impl ToString) -> Difference {
+    Difference {
+        field: field.to_string(),
+        original: original.to_string(),
+        replayed: replayed.to_string(),
+    }
+}
+
+// everything but the run id and start time, which a replay never shares with
+// the original; records are compared where both summaries have them
+pub fn compare(original: &Summary, replayed: &Summary, tolerance: f32) -> Vec<Difference> {
+    let mut res = vec![];
+    let mut floats = |field: &str, a: f32, b: f32| {
+        if (a - b).abs() > tolerance {
+            res.push(difference(field, a, b));
+        }
+    };
+    floats(
+        "totalDistance",
+        original.total_distance,
+        replayed.total_distance,
+    );
+    floats(
+        "totalCalories",
+        original.total_calories,
+        replayed.total_calories,
+    );
+    floats(
+        "maxRectangle.height",
+        original.largest_rect.height,
+        replayed.largest_rect.height,
+    );
+    let mut exact = |field: &str, a: u32, b: u32| {
+        if a != b {
+            res.push(difference(field, a, b));
+        }
+    };
+    exact(
+        "totalTime",
+        original.total_time_secs,
+        replayed.total_time_secs,
+    );
+    exact(
+        "debounce.droppedTicks",
+        original.debounce.dropped_ticks as u32,
+        replayed.debounce.dropped_ticks as u32,
+    );
+    exact(
+        "maxRectangle.start",
+        original.largest_rect.start_time,
+        replayed.largest_rect.start_time,
+    );
+    exact(
+        "maxRectangle.end",
+        original.largest_rect.end_time,
+        replayed.largest_rect.end_time,
+    );
+    compare_records(
+        &original.distance_records,
+        &replayed.distance_records,
+        &mut res,
+    );
+    res
+}
+
+// the stored totals and records of the original run, as listed in its athlete's
+// history, against a replay of its archive
+pub fn compare_stored(stored: &RunListing, replayed: &Summary, tolerance: f32) -> Vec<Difference> {
+    let mut res = vec![];
+    let mut floats = |field: &str, a: f32, b: f32| {
+        if (a - b).abs() > tolerance {
+            res.push(difference(field, a, b));
+        }
+    };
+    floats(
+        "totalDistance",
+        stored.total_distance,
+        replayed.total_distance,
+    );
+    floats(
+        "totalCalories",
+        stored.total_calories,
+        replayed.total_calories,
+    );
+    if stored.total_time_secs != replayed.total_time_secs {
+        res.push(difference(
+            "totalTime",
+            stored.total_time_secs,
+            replayed.total_time_secs,
+        ));
+    }
+    compare_records(
+        &stored.distance_records,
+        &replayed.distance_records,
+        &mut res,
+    );
+    res
+}
+
+fn compare_records(
+    original: &DistanceRecordSet,
+    replayed: &DistanceRecordSet,
+    res: &mut Vec<Difference>,
+) {
+    let mut names: Vec<&String> = original
+        .0
+        .keys()
+        .filter(|name| replayed.0.contains_key(*name))
+        .collect();
+    names.sort();
+    for name in names {
+        let field = format!("bestDistances.{}", name);
+        match (&original.0[name], &replayed.0[name]) {
+            (Some(a), Some(b)) => {
+                for (what, a, b) in [
+                    ("time", a.time, b.time),
+                    ("left", a.start_time, b.start_time),
+                    ("right", a.end_time, b.end_time),
+                ] {
+                    if a != b {
+                        res.push(difference(&format!("{}.{}", field, what), a, b));
+                    }
+                }
+            }
+            (None, None) => (),
+            (a, b) => res.push(difference(
+                &field,
+                if a.is_some() { "set" } else { "none" },
+                if b.is_some() { "set" } else { "none" },
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{run::RawData, test_fixtures::athlete};
+
+    fn summarize(tickstamps: Vec<Tickstamp>) -> Summary<'static> {
+        let raw_data = RawData {
+            start_time: "0".to_string(),
+            tickstamps,
+            clock_samples: vec![],
+            discontinuities: vec![],
+        };
+        Summary::new("replay", &athlete(), raw_data).unwrap()
+    }
+
+    #[test]
+    fn batches_keep_original_pacing() {
+        let batches = replay_batches(&[5000, 5400, 5900, 6300, 6800], 2);
+        assert_eq!(
+            batches,
+            vec![
+                Batch {
+                    send_at: 400,
+                    device_time: 5400,
+                    ticks: vec![5000, 5400]
+                },
+                Batch {
+                    send_at: 1300,
+                    device_time: 6300,
+                    ticks: vec![5900, 6300]
+                },
+                Batch {
+                    send_at: 1800,
+                    device_time: 6800,
+                    ticks: vec![6800]
+                },
+            ]
+        );
+        assert_eq!(replay_batches(&[], 10), vec![]);
+    }
+
+    #[test]
+    fn compare_summaries() {
+        // just over a mile at about 6 mph
+        let ticks: Vec<Tickstamp> = (0..10400).map(|i| 1000 + i * 60).collect();
+        let original = summarize(ticks.clone());
+        assert_eq!(compare(&original, &summarize(ticks.clone()), 0.001), vec![]);
+
+        // the replay lost its last 300 ticks, falling short of a mile
+        let replayed = summarize(ticks[..ticks.len() - 300].to_vec());
+        let differences = compare(&original, &replayed, 0.001);
+        let fields: Vec<&str> = differences.iter().map(|d| d.field.as_str()).collect();
+        assert!(fields.contains(&"totalDistance"));
+        assert!(fields.contains(&"totalTime"));
+        assert!(fields.contains(&"bestDistances.oneMile"));
+        assert!(!fields.contains(&"bestDistances.lap"));
+    }
+
+    #[test]
+    fn compare_with_stored_summary() {
+        let ticks: Vec<Tickstamp> = (0..10400).map(|i| 1000 + i * 60).collect();
+        let original = summarize(ticks.clone());
+        let stored = RunListing {
+            id: original.id.to_string(),
+            start_time: original.start_time.clone(),
+            total_time_secs: original.total_time_secs,
+            total_calories: original.total_calories,
+            total_distance: original.total_distance,
+            athlete_id: original.athlete_id.clone(),
+            distance_records: summarize(ticks.clone()).distance_records,
+            new_personal_bests: original.new_personal_bests.clone(),
+        };
+        assert_eq!(compare_stored(&stored, &original, 0.001), vec![]);
+
+        let replayed = summarize(ticks[..ticks.len() - 300].to_vec());
+        let differences = compare_stored(&stored, &replayed, 0.001);
+        let fields: Vec<&str> = differences.iter().map(|d| d.field.as_str()).collect();
+        assert!(fields.contains(&"totalDistance"));
+        assert!(fields.contains(&"totalTime"));
+        assert!(fields.contains(&"bestDistances.oneMile"));
+        assert!(!fields.contains(&"bestDistances.lap"));
+    }
+}
@@ -0,0 +1,152 @@
+use rocket::serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::{
+    athlete::Athlete,
+    aws::{
+        fetch_all_runs, fetch_athlete, fetch_personal_records, fetch_run, push_personal_records,
+        push_summary_to_db, read_archive_from_s3, update_personal_records,
+    },
+    config,
+    error::{ApiError, ErrorCode},
+    records::PersonalRecords,
+    run::{RunListing, Summary, SummaryOptions},
+};
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct FailedRun {
+    #[serde(rename = "runId")]
+    pub run_id: String,
+    #[serde(rename = "message")]
+    pub message: String,
+}
+
+// an athlete whose rebuilt personal records couldn't be stored
+#[derive(Serialize, Debug, PartialEq)]
+pub struct FailedAthlete {
+    #[serde(rename = "athleteId")]
+    pub athlete_id: String,
+    #[serde(rename = "message")]
+    pub message: String,
+}
+
+#[derive(Serialize, Debug, PartialEq, Default)]
+pub struct ResummarizeReport {
+    #[serde(rename = "resummarized")]
+    pub resummarized: usize,
+    #[serde(rename = "failed")]
+    pub failed: Vec<FailedRun>,
+    #[serde(rename = "failedAthletes")]
+    pub failed_athletes: Vec<FailedAthlete>,
+}
+
+impl ResummarizeReport {
+    fn fail(&mut self, run_id: &str, e: ApiError) {
+        self.failed.push(FailedRun {
+            run_id: run_id.to_string(),
+            message: e.message,
+        });
+    }
+
+    fn fail_athlete(&mut self, athlete_id: &str, e: ApiError) {
+        self.failed_athletes.push(FailedAthlete {
+            athlete_id: athlete_id.to_string(),
+            message: e.message,
+        });
+    }
+}
+
+pub async fn summarize_archive<'a>(
+    run_id: &'a str,
+    athlete: &Athlete,
+    options: &SummaryOptions,
+) -> Result<Summary<'a>, ApiError> {
+    let raw_data = read_archive_from_s3(run_id).await?.raw_data()?;
+    Ok(Summary::with_options(run_id, athlete, raw_data, options)?)
+}
+
+pub async fn fetch_run_athlete(run: &RunListing) -> Result<Athlete, ApiError> {
+    fetch_athlete(&run.athlete_id).await?.ok_or_else(|| {
+        ApiError::new(
+            ErrorCode::AthleteNotFound,
+            format!("no athlete with id {:?}", run.athlete_id),
+        )
+    })
+}
+
+// recomputes a stored run from its raw archive and overwrites its summary and its
+// entries in the athlete's personal records; which records were new when the run
+// happened is kept as it was
+pub async fn one(run_id: &str) -> Result<Summary<'_>, ApiError> {
+    let run = fetch_run(run_id)
+        .await?
+        .ok_or_else(|| ApiError::new(ErrorCode::RunNotFound, "no stored summary for this run"))?;
+    let athlete = fetch_run_athlete(&run).await?;
+    let mut summary = summarize_archive(run_id, &athlete, &config::get().analysis).await?;
+    summary.new_personal_bests = run.new_personal_bests;
+    update_personal_records(&athlete.id, &summary).await?;
+    push_summary_to_db(&summary).await?;
+    Ok(summary)
+}
+
+// recomputes every stored run, replaying each athlete's runs in order so that
+// personal records and the new-record flags are rebuilt from scratch as well
+pub async fn all() -> Result<ResummarizeReport, ApiError> {
+    let mut by_athlete: BTreeMap<String, Vec<RunListing>> = BTreeMap::new();
+    for run in fetch_all_runs().await? {
+        by_athlete
+            .entry(run.athlete_id.clone())
+            .or_default()
+            .push(run);
+    }
+
+    let mut report = ResummarizeReport::default();
+    for runs in by_athlete.values_mut() {
+        runs.sort_by_key(|r| r.start_time.parse::<u64>().unwrap_or_default());
+        let athlete = match fetch_run_athlete(&runs[0]).await {
+            Ok(a) => a,
+            Err(e) => {
+                for run in runs.iter() {
+                    report.fail(&run.id, ApiError::new(e.code, e.message.clone()));
+                }
+                continue;
+            }
+        };
+
+        // the records are rebuilt from scratch, but only stored if no run was
+        // finished in the meantime, which would be missing from them
+        let version = fetch_personal_records(&athlete.id).await.map(|(_, v)| v);
+        let mut personal_records = PersonalRecords::default();
+        for run in runs.iter() {
+            let mut summary =
+                match summarize_archive(&run.id, &athlete, &config::get().analysis).await {
+                    Ok(s) => s,
+                    Err(e) => {
+                        report.fail(&run.id, e);
+                        continue;
+                    }
+                };
+            summary.new_personal_bests = personal_records.update(&summary);
+            match push_summary_to_db(&summary).await {
+                Ok(()) => report.resummarized += 1,
+                Err(e) => report.fail(&run.id, e.into()),
+            }
+        }
+        let pushed = match version {
+            Ok(version) => push_personal_records(&athlete.id, &personal_records, version).await,
+            Err(e) => Err(e),
+        };
+        match pushed {
+            Ok(true) => (),
+            Ok(false) => report.fail_athlete(
+                &athlete.id,
+                ApiError::new(
+                    ErrorCode::StorageUnavailable,
+                    "personal records changed while they were rebuilt",
+                ),
+            ),
+            Err(e) => report.fail_athlete(&athlete.id, e.into()),
+        }
+    }
+    Ok(report)
+}
@@ -0,0 +1,58 @@
+use rocket::serde::{json::Json, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    athlete::{Athlete, NewAthlete},
+    auth::{AdminKey, ApiKey, KeyScope, UserKey},
+    aws::{fetch_athlete, fetch_runs_for_athlete, push_api_key, push_athlete_to_db},
+    error::{ApiError, ErrorCode},
+    run::RunListing,
+};
+
+#[derive(Serialize)]
+pub struct NewAthleteCreated {
+    #[serde(flatten)]
+    athlete: Athlete,
+    #[serde(rename = "apiKey")]
+    api_key: String,
+}
+
+// user keys may only read the athlete they were issued for
+pub async fn own_athlete(user: &UserKey, athlete_id: &str) -> Result<Athlete, ApiError> {
+    if user.athlete_id != athlete_id {
+        return Err(ApiError::new(
+            ErrorCode::Forbidden,
+            format!("api key does not belong to athlete {}", athlete_id),
+        ));
+    }
+    fetch_athlete(athlete_id).await?.ok_or_else(|| {
+        ApiError::new(
+            ErrorCode::AthleteNotFound,
+            format!("no athlete with id {}", athlete_id),
+        )
+    })
+}
+
+#[post("/athlete", data = "<new_athlete>")]
+pub async fn new_athlete(
+    _admin: AdminKey,
+    new_athlete: Json<NewAthlete>,
+) -> Result<Json<NewAthleteCreated>, ApiError> {
+    let id = format!("{}", Uuid::new_v4());
+    let athlete = Athlete::new(id, new_athlete.into_inner())?;
+    push_athlete_to_db(&athlete).await?;
+    let (api_key, key) = ApiKey::issue(&athlete.name, KeyScope::User(athlete.id.clone()));
+    push_api_key(&key).await?;
+    Ok(Json(NewAthleteCreated { athlete, api_key }))
+}
+
+#[get("/athlete/<athlete_id>")]
+pub async fn get_athlete(user: UserKey, athlete_id: &str) -> Result<Json<Athlete>, ApiError> {
+    own_athlete(&user, athlete_id).await.map(Json)
+}
+
+#[get("/athlete/<athlete_id>/runs")]
+pub async fn list_runs(user: UserKey, athlete_id: &str) -> Result<Json<Vec<RunListing>>, ApiError> {
+    own_athlete(&user, athlete_id).await?;
+    Ok(Json(fetch_runs_for_athlete(athlete_id).await?))
+}
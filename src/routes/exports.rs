@@ -0,0 +1,172 @@
+use rocket::{
+    http::{ContentType, Header},
+    response::{self, stream::TextStream, Responder},
+    Request, Response,
+};
+use std::io::Cursor;
+
+use crate::{
+    athlete::{Athlete, Units},
+    auth::UserKey,
+    aws::fetch_run_page,
+    csv,
+    error::{ApiError, ErrorCode},
+    export, fit,
+    resummarize::{fetch_run_athlete, summarize_archive},
+    routes::{
+        athletes::own_athlete,
+        run_data::{interval_options, own_run},
+    },
+    run::Summary,
+};
+
+// a file the client is meant to save rather than display
+pub struct Download {
+    content_type: ContentType,
+    filename: String,
+    body: Vec<u8>,
+}
+
+impl<'r> Responder<'r, 'static> for Download {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        Response::build()
+            .header(self.content_type)
+            .header(Header::new(
+                "Content-Disposition",
+                format!("attachment; filename=\"{}\"", self.filename),
+            ))
+            .sized_body(self.body.len(), Cursor::new(self.body))
+            .ok()
+    }
+}
+
+async fn exported_run<'a>(
+    user: &UserKey,
+    run_id: &'a str,
+    interval: Option<u32>,
+) -> Result<(Summary<'a>, Athlete), ApiError> {
+    let options = interval_options(interval)?;
+    let run = own_run(user, run_id).await?;
+    let athlete = fetch_run_athlete(&run).await?;
+    let summary = summarize_archive(run_id, &athlete, &options).await?;
+    Ok((summary, athlete))
+}
+
+#[get("/run/<run_id>/tcx?<interval>")]
+pub async fn get_tcx(
+    user: UserKey,
+    run_id: &str,
+    interval: Option<u32>,
+) -> Result<Download, ApiError> {
+    let (summary, athlete) = exported_run(&user, run_id, interval)
+        .await
+        .map_err(|e| e.with_run(run_id))?;
+    Ok(Download {
+        content_type: ContentType::new("application", "vnd.garmin.tcx+xml"),
+        filename: format!("{}.tcx", run_id),
+        body: export::tcx(&summary, athlete.units.split_distance()).into_bytes(),
+    })
+}
+
+#[get("/run/<run_id>/gpx?<interval>")]
+pub async fn get_gpx(
+    user: UserKey,
+    run_id: &str,
+    interval: Option<u32>,
+) -> Result<Download, ApiError> {
+    let (summary, _) = exported_run(&user, run_id, interval)
+        .await
+        .map_err(|e| e.with_run(run_id))?;
+    Ok(Download {
+        content_type: ContentType::new("application", "gpx+xml"),
+        filename: format!("{}.gpx", run_id),
+        body: export::gpx(&summary).into_bytes(),
+    })
+}
+
+#[get("/run/<run_id>/fit?<interval>")]
+pub async fn get_fit(
+    user: UserKey,
+    run_id: &str,
+    interval: Option<u32>,
+) -> Result<Download, ApiError> {
+    let (summary, athlete) = exported_run(&user, run_id, interval)
+        .await
+        .map_err(|e| e.with_run(run_id))?;
+    Ok(Download {
+        content_type: ContentType::new("application", "vnd.ant.fit"),
+        filename: format!("{}.fit", run_id),
+        body: fit::encode(&summary, athlete.units.split_distance()),
+    })
+}
+
+// csv columns use the athlete's units unless others are asked for
+fn csv_units(units: Option<&str>, athlete: &Athlete) -> Result<Units, ApiError> {
+    match units {
+        Some(name) => Units::from_name(name).ok_or_else(|| {
+            ApiError::new(ErrorCode::BadRequest, format!("unknown units: {:?}", name))
+        }),
+        None => Ok(athlete.units),
+    }
+}
+
+// rows are written a page of runs at a time; the first page is read up front so
+// that a failing query is still reported as an error
+#[get("/athlete/<athlete_id>/runs/csv?<units>&<from>&<to>")]
+pub async fn list_runs_csv(
+    user: UserKey,
+    athlete_id: &str,
+    units: Option<&str>,
+    from: Option<u64>,
+    to: Option<u64>,
+) -> Result<(ContentType, TextStream![String]), ApiError> {
+    let athlete = own_athlete(&user, athlete_id).await?;
+    let units = csv_units(units, &athlete)?;
+    let record_names = csv::record_names(&athlete);
+    let (mut page, mut next) = fetch_run_page(&athlete.id, from, to, None).await?;
+    let stream = TextStream! {
+        yield csv::run_header(units, &record_names);
+        loop {
+            for run in &page {
+                yield csv::run_row(run, units, &record_names);
+            }
+            let start_key = match next.take() {
+                Some(key) => key,
+                None => break,
+            };
+            match fetch_run_page(&athlete.id, from, to, Some(start_key)).await {
+                Ok((p, n)) => {
+                    page = p;
+                    next = n;
+                }
+                // the status is already sent, so a failing page ends the file early
+                Err(e) => {
+                    error!("error streaming runs of athlete {}: {}", athlete.id, e.msg);
+                    break;
+                }
+            }
+        }
+    };
+    Ok((ContentType::CSV, stream))
+}
+
+#[get("/run/<run_id>/csv?<units>&<interval>")]
+pub async fn get_series_csv(
+    user: UserKey,
+    run_id: &str,
+    units: Option<&str>,
+    interval: Option<u32>,
+) -> Result<(ContentType, TextStream![String]), ApiError> {
+    let (summary, athlete) = exported_run(&user, run_id, interval)
+        .await
+        .map_err(|e| e.with_run(run_id))?;
+    let units = csv_units(units, &athlete).map_err(|e| e.with_run(run_id))?;
+    let interval_data = summary.interval_data;
+    let stream = TextStream! {
+        yield csv::series_header(units);
+        for datum in &interval_data {
+            yield csv::series_row(datum, units);
+        }
+    };
+    Ok((ContentType::CSV, stream))
+}
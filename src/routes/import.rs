@@ -0,0 +1,61 @@
+use rocket::{
+    data::{Data, ToByteUnit},
+    http::Status,
+    serde::json::{serde_json::Value, Json},
+};
+
+use crate::{
+    auth::AdminKey,
+    aws::fetch_athlete,
+    error::{ApiError, ErrorCode},
+    import::{self, DistanceSeries, ImportFormat},
+};
+
+const IMPORT_LIMIT_MIB: usize = 64;
+
+// the file is the request body; csv files also need the run's start time in epoch millis.
+// importing writes runs and personal records, which user keys may only read
+#[post("/athlete/<athlete_id>/import?<format>&<start_time>", data = "<data>")]
+pub async fn import_run(
+    _admin: AdminKey,
+    athlete_id: &str,
+    format: &str,
+    start_time: Option<u64>,
+    data: Data<'_>,
+) -> Result<(Status, Json<Value>), ApiError> {
+    let athlete = fetch_athlete(athlete_id).await?.ok_or_else(|| {
+        ApiError::new(
+            ErrorCode::AthleteNotFound,
+            format!("no athlete with id {}", athlete_id),
+        )
+    })?;
+    let format = ImportFormat::from_name(format).ok_or_else(|| {
+        ApiError::new(
+            ErrorCode::BadRequest,
+            format!("unknown import format: {:?}", format),
+        )
+    })?;
+    let text = data
+        .open(IMPORT_LIMIT_MIB.mebibytes())
+        .into_string()
+        .await
+        .map_err(|e| ApiError::new(ErrorCode::BadRequest, format!("error reading file: {}", e)))?;
+    if !text.is_complete() {
+        return Err(ApiError::new(
+            ErrorCode::BadRequest,
+            format!("file is larger than {} MiB", IMPORT_LIMIT_MIB),
+        ));
+    }
+    let series = DistanceSeries::parse(format, &text, start_time)?;
+    let run_id = import::run_id(&athlete.id, series.start_time);
+    let summary = import::store(&run_id, &athlete, format, &series)
+        .await
+        .map_err(|e| e.with_run(&run_id))?;
+    let summary = rocket::serde::json::to_value(&summary).map_err(|e| {
+        ApiError::new(
+            ErrorCode::Internal,
+            format!("error encoding summary: {}", e),
+        )
+    })?;
+    Ok((Status::Created, Json(summary)))
+}
@@ -0,0 +1,33 @@
+use rocket::http::Status;
+
+use crate::{
+    auth::{AdminKey, ApiKey, KeyScope},
+    aws::{fetch_athlete, push_api_key},
+    error::{ApiError, ErrorCode},
+};
+
+// issues a fresh ingestion key for a treadmill; the plain key is only returned here
+#[post("/device-key?<name>")]
+pub async fn new_device_key(_admin: AdminKey, name: &str) -> Result<(Status, String), ApiError> {
+    let (api_key, key) = ApiKey::issue(name, KeyScope::Device);
+    push_api_key(&key).await?;
+    Ok((Status::Created, api_key))
+}
+
+// issues an additional read key for an athlete, e.g. for a second client
+#[post("/athlete/<athlete_id>/key?<name>")]
+pub async fn new_user_key(
+    _admin: AdminKey,
+    athlete_id: &str,
+    name: &str,
+) -> Result<(Status, String), ApiError> {
+    if fetch_athlete(athlete_id).await?.is_none() {
+        return Err(ApiError::new(
+            ErrorCode::AthleteNotFound,
+            format!("no athlete with id {}", athlete_id),
+        ));
+    }
+    let (api_key, key) = ApiKey::issue(name, KeyScope::User(athlete_id.to_string()));
+    push_api_key(&key).await?;
+    Ok((Status::Created, api_key))
+}
@@ -1,3 +1,15 @@
+pub use self::athletes::{get_athlete, list_runs, new_athlete};
+pub use self::exports::{get_fit, get_gpx, get_series_csv, get_tcx, list_runs_csv};
+pub use self::import::import_run;
+pub use self::keys::{new_device_key, new_user_key};
+pub use self::run_data::{get_raw, get_series};
+pub use self::resummarize::{resummarize_all, resummarize_run};
 pub use self::run_progress::{finalize_run, new_run, post_data};
 
-mod run_progress;
\ No newline at end of file
+mod athletes;
+mod exports;
+mod import;
+mod keys;
+mod resummarize;
+mod run_data;
+mod run_progress;
@@ -0,0 +1,24 @@
+use rocket::serde::json::Json;
+
+use crate::{
+    auth::AdminKey,
+    error::ApiError,
+    resummarize::{self, ResummarizeReport},
+    run::Summary,
+};
+
+#[post("/run/<run_id>/resummarize")]
+pub async fn resummarize_run<'a>(
+    _admin: AdminKey,
+    run_id: &'a str,
+) -> Result<Json<Summary<'a>>, ApiError> {
+    resummarize::one(run_id)
+        .await
+        .map(Json)
+        .map_err(|e| e.with_run(run_id))
+}
+
+#[post("/resummarize")]
+pub async fn resummarize_all(_admin: AdminKey) -> Result<Json<ResummarizeReport>, ApiError> {
+    resummarize::all().await.map(Json)
+}
@@ -0,0 +1,99 @@
+use rocket::serde::{json::Json, Serialize};
+
+use crate::{
+    auth::UserKey,
+    aws::{fetch_run, read_archive_from_s3},
+    config,
+    error::{ApiError, ErrorCode},
+    resummarize::{fetch_run_athlete, summarize_archive},
+    run::{IntervalDatum, RunListing, SummaryOptions, Tickstamp},
+};
+
+#[derive(Serialize)]
+pub struct RawRun {
+    #[serde(rename = "runId")]
+    run_id: String,
+    #[serde(rename = "startTime")]
+    start_time: String,
+    #[serde(rename = "archiveVersion")]
+    archive_version: u32,
+    #[serde(rename = "ticks")]
+    ticks: Vec<Tickstamp>,
+}
+
+// user keys may only read runs of the athlete they were issued for
+pub async fn own_run(user: &UserKey, run_id: &str) -> Result<RunListing, ApiError> {
+    let run = fetch_run(run_id)
+        .await?
+        .ok_or_else(|| ApiError::new(ErrorCode::RunNotFound, "no stored summary for this run"))?;
+    if run.athlete_id != user.athlete_id {
+        return Err(ApiError::new(
+            ErrorCode::Forbidden,
+            "run belongs to another athlete",
+        ));
+    }
+    Ok(run)
+}
+
+// series are resummarized at the requested resolution, in millis per interval
+pub fn interval_options(interval: Option<u32>) -> Result<SummaryOptions, ApiError> {
+    let config = config::get();
+    let (min, max) = (config.runs.min_interval_size, config.runs.max_interval_size);
+    let mut options = config.analysis;
+    match interval {
+        Some(i) if !(min..=max).contains(&i) => Err(ApiError::new(
+            ErrorCode::BadRequest,
+            format!("interval must be between {} and {} ms, got {}", min, max, i),
+        )),
+        Some(i) => {
+            options.interval_size = i;
+            Ok(options)
+        }
+        None => Ok(options),
+    }
+}
+
+#[get("/run/<run_id>/raw")]
+pub async fn get_raw(user: UserKey, run_id: &str) -> Result<Json<RawRun>, ApiError> {
+    raw(&user, run_id)
+        .await
+        .map(Json)
+        .map_err(|e| e.with_run(run_id))
+}
+
+async fn raw(user: &UserKey, run_id: &str) -> Result<RawRun, ApiError> {
+    own_run(user, run_id).await?;
+    let archive = read_archive_from_s3(run_id).await?;
+    let raw_data = archive.raw_data()?;
+    Ok(RawRun {
+        run_id: run_id.to_string(),
+        start_time: raw_data.start_time,
+        archive_version: archive.version,
+        ticks: raw_data.tickstamps,
+    })
+}
+
+#[get("/run/<run_id>/series?<interval>")]
+pub async fn get_series(
+    user: UserKey,
+    run_id: &str,
+    interval: Option<u32>,
+) -> Result<Json<Vec<IntervalDatum>>, ApiError> {
+    series(&user, run_id, interval)
+        .await
+        .map(Json)
+        .map_err(|e| e.with_run(run_id))
+}
+
+pub async fn series(
+    user: &UserKey,
+    run_id: &str,
+    interval: Option<u32>,
+) -> Result<Vec<IntervalDatum>, ApiError> {
+    let options = interval_options(interval)?;
+    let run = own_run(user, run_id).await?;
+    let athlete = fetch_run_athlete(&run).await?;
+    Ok(summarize_archive(run_id, &athlete, &options)
+        .await?
+        .interval_data)
+}
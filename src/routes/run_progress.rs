@@ -1,126 +1,337 @@
 use std::time::UNIX_EPOCH;
 use uuid::Uuid;
-use rocket::{serde::json::Json, http::Status};
+use rocket::{serde::json::{self, Json}, http::Status};
 
 use crate::{
+    archive::{Archive, IngestionMetadata},
+    auth::DeviceKey,
     cache,
-    run::{self, Summary, Tickstamp}, aws::{write_data_to_s3, push_summary_to_db},
+    clock::ClockSample,
+    config,
+    error::{ApiError, ErrorCode},
+    run::{self, Summary, Tickstamp},
+    timeline::{self, Timeline},
+    aws::{
+        fetch_athlete, fetch_personal_records, push_summary_to_db, summary_exists,
+        update_personal_records, write_data_to_s3,
+    },
 };
 
 fn start_time_key(run_id: &str) -> String {
     format!("{}-{}", "start_time", run_id)
 }
 
-#[get("/new-run")]
-pub fn new_run<'a>() -> (Status, String) {
-    let id = format!("{}", Uuid::new_v4());
-    let start_time = std::time::SystemTime::now()
+fn athlete_key(run_id: &str) -> String {
+    format!("{}-{}", "athlete", run_id)
+}
+
+fn device_key(run_id: &str) -> String {
+    format!("{}-{}", "device", run_id)
+}
+
+fn finalize_stage_key(run_id: &str) -> String {
+    format!("{}-{}", "finalize_stage", run_id)
+}
+
+fn personal_bests_key(run_id: &str) -> String {
+    format!("{}-{}", "personal_bests", run_id)
+}
+
+fn clock_key(run_id: &str) -> String {
+    format!("{}-{}", "clock", run_id)
+}
+
+fn timeline_key(run_id: &str) -> String {
+    format!("{}-{}", "timeline", run_id)
+}
+
+// finalizing records how far it got, so a repeated finish resumes after the last
+// completed stage instead of redoing (or losing) work
+#[derive(Debug, PartialEq, PartialOrd, Clone, Copy)]
+enum FinalizeStage {
+    Pending,
+    Computed,
+    RawArchived,
+    SummaryStored,
+}
+
+impl FinalizeStage {
+    fn name(&self) -> &'static str {
+        match self {
+            FinalizeStage::Pending => "pending",
+            FinalizeStage::Computed => "computed",
+            FinalizeStage::RawArchived => "rawArchived",
+            FinalizeStage::SummaryStored => "summaryStored",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<FinalizeStage> {
+        match name {
+            "pending" => Some(FinalizeStage::Pending),
+            "computed" => Some(FinalizeStage::Computed),
+            "rawArchived" => Some(FinalizeStage::RawArchived),
+            "summaryStored" => Some(FinalizeStage::SummaryStored),
+            _ => None,
+        }
+    }
+}
+
+fn advance(run_id: &str, stage: FinalizeStage) -> Result<FinalizeStage, ApiError> {
+    cache::set(finalize_stage_key(run_id), stage.name().to_string())?;
+    Ok(stage)
+}
+
+// the last stage; once the device key is gone the run reads as finalized
+fn clear_run_cache(run_id: &str) -> Result<(), ApiError> {
+    cache::zrem(run_id)?;
+    cache::del(&start_time_key(run_id))?;
+    cache::del(&athlete_key(run_id))?;
+    cache::del(&personal_bests_key(run_id))?;
+    cache::del(&clock_key(run_id))?;
+    cache::del(&timeline_key(run_id))?;
+    cache::del(&finalize_stage_key(run_id))?;
+    cache::del(&device_key(run_id))?;
+    Ok(())
+}
+
+fn now_millis() -> u64 {
+    std::time::SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .expect("bad time")
-        .as_millis();
-    let start_time = format!("{}", start_time);
-    match cache::set(start_time_key(&id), start_time) {
-        Ok(_) => (Status::Accepted, id),
-        Err(e) => (
-            Status::InternalServerError,
-            format!("cache error: {}", e.msg),
-        ),
+        .as_millis() as u64
+}
+
+fn load_timeline(run_id: &str) -> Result<Timeline, ApiError> {
+    match cache::get(timeline_key(run_id))? {
+        Some(timeline) => json::from_str(&timeline).map_err(|e| {
+            ApiError::new(ErrorCode::Internal, format!("error decoding timeline: {}", e))
+        }),
+        None => Ok(Timeline::default()),
+    }
+}
+
+fn store_timeline(run_id: &str, timeline: &Timeline) -> Result<(), ApiError> {
+    let timeline = json::to_string(timeline).map_err(|e| {
+        ApiError::new(ErrorCode::Internal, format!("error encoding timeline: {}", e))
+    })?;
+    cache::set(timeline_key(run_id), timeline)?;
+    Ok(())
+}
+
+// pairs the device's millis counter with the time it reached us
+fn record_clock_sample(
+    run_id: &str,
+    timeline: &mut Timeline,
+    device_time: Option<Tickstamp>,
+) -> Result<(), ApiError> {
+    if let Some(device_time) = device_time {
+        let device_time = timeline.stitch(device_time, config::get().runs.max_tick_rewind);
+        let member = ClockSample::encode(device_time, now_millis());
+        cache::zadd_multiple(&clock_key(run_id), vec![(&member, device_time)])?;
     }
+    Ok(())
+}
+
+fn missing_run_key(run_id: &str, key: &str) -> ApiError {
+    ApiError::new(ErrorCode::Internal, format!("run is missing its {} in cache", key))
+        .with_run(run_id)
 }
 
-#[post("/run/<run_id>", data = "<post_data>")]
-pub fn post_data<'a>(run_id: &str, post_data: &str) -> (Status, String) {
-    let item_pairs: Vec<(&str, u64)> = post_data
+// runs only accept ticks from the device that started them; once a run is
+// finalized its cache keys are gone and it is reported as a conflict
+async fn check_run_device(run_id: &str, device: &DeviceKey) -> Result<(), ApiError> {
+    match cache::get(device_key(run_id))? {
+        Some(key_hash) if key_hash == device.key_hash => Ok(()),
+        Some(_) => Err(ApiError::new(
+            ErrorCode::Forbidden,
+            "run was started by another device",
+        )),
+        None => match summary_exists(run_id).await? {
+            true => Err(ApiError::new(ErrorCode::RunFinalized, "run is already finalized")),
+            false => Err(ApiError::new(ErrorCode::RunNotFound, "no run with this id")),
+        },
+    }
+    .map_err(|e| e.with_run(run_id))
+}
+
+fn parse_ticks(post_data: &str) -> Result<Vec<Tickstamp>, ApiError> {
+    post_data
         .split(',')
-        .map(|t| {
-            let score: u64 = t.trim().parse().expect("msg");
-            (t.trim(), score)
+        .map(|t| match t.trim().parse() {
+            Ok(tick) => Ok(tick),
+            Err(_) => Err(ApiError::new(
+                ErrorCode::BadRequest,
+                format!("invalid tickstamp: {:?}", t.trim()),
+            )),
         })
-        .collect();
+        .collect()
+}
 
-    match cache::zadd_multiple(run_id, item_pairs) {
-        Ok(()) => (Status::Accepted, "".to_string()),
-        Err(e) => (
-            Status::InternalServerError,
-            format!("cache error: {}", e.msg),
-        ),
+#[get("/new-run?<athlete_id>&<device_time>")]
+pub async fn new_run(
+    device: DeviceKey,
+    athlete_id: &str,
+    device_time: Option<Tickstamp>,
+) -> Result<(Status, String), ApiError> {
+    if fetch_athlete(athlete_id).await?.is_none() {
+        return Err(ApiError::new(
+            ErrorCode::AthleteNotFound,
+            format!("no athlete with id {}", athlete_id),
+        ));
     }
+    let id = format!("{}", Uuid::new_v4());
+    cache::set(athlete_key(&id), athlete_id.to_string())?;
+    cache::set(device_key(&id), device.key_hash)?;
+    let start_time = format!("{}", now_millis());
+    cache::set(start_time_key(&id), start_time)?;
+    let mut timeline = Timeline::default();
+    record_clock_sample(&id, &mut timeline, device_time)?;
+    store_timeline(&id, &timeline)?;
+    Ok((Status::Accepted, id))
 }
 
-#[derive(Responder)]
-pub enum FinalizeRunResponse<'a> {
-    #[response(status = 200)]
-    Success(Json<Summary<'a>>),
-    #[response(status = 500, content_type = "json")]
-    Error(String),
+#[post("/run/<run_id>?<device_time>", data = "<post_data>")]
+pub async fn post_data(
+    device: DeviceKey,
+    run_id: &str,
+    device_time: Option<Tickstamp>,
+    post_data: &str,
+) -> Result<(Status, String), ApiError> {
+    check_run_device(run_id, &device).await?;
+    let ticks = parse_ticks(post_data).map_err(|e| e.with_run(run_id))?;
+    // ticks are cached at their place on the run's timeline, which only moves
+    // past a counter that started over
+    let mut timeline = load_timeline(run_id).map_err(|e| e.with_run(run_id))?;
+    let max_rewind = config::get().runs.max_tick_rewind;
+    let members: Vec<(String, u64)> = ticks
+        .into_iter()
+        .map(|t| {
+            let time = timeline.stitch(t, max_rewind);
+            (time.to_string(), time)
+        })
+        .collect();
+    let item_pairs = members.iter().map(|(m, t)| (m.as_str(), *t)).collect();
+    cache::zadd_multiple(run_id, item_pairs).map_err(|e| ApiError::from(e).with_run(run_id))?;
+    record_clock_sample(run_id, &mut timeline, device_time).map_err(|e| e.with_run(run_id))?;
+    store_timeline(run_id, &timeline).map_err(|e| e.with_run(run_id))?;
+    Ok((Status::Accepted, "".to_string()))
 }
 
 #[post("/run/<run_id>/finish")]
-pub async fn finalize_run<'a>(run_id: &'a str) -> FinalizeRunResponse<'a> {
-    let tickstamp_data = match cache::fullzrange(run_id) {
-        Ok(d) => d,
-        Err(e) => {
-            return FinalizeRunResponse::Error(format!(
-                "error fetching tickstamps from cache: {}",
-                e.msg
-            ))
-        }
-    };
-    let start_time = match cache::get(start_time_key(run_id)) {
-        Ok(t) => t,
-        Err(e) => {
-            return FinalizeRunResponse::Error(format!(
-                "error fetching start time from cache: {}",
-                e.msg
-            ))
-        }
+pub async fn finalize_run<'a>(
+    device: DeviceKey,
+    run_id: &'a str,
+) -> Result<Json<Summary<'a>>, ApiError> {
+    check_run_device(run_id, &device).await?;
+    finalize(run_id).await.map(Json).map_err(|e| e.with_run(run_id))
+}
+
+async fn finalize(run_id: &str) -> Result<Summary<'_>, ApiError> {
+    let mut stage = match cache::get(finalize_stage_key(run_id))? {
+        Some(name) => FinalizeStage::from_name(&name)
+            .ok_or_else(|| missing_run_key(run_id, "finalize stage"))?,
+        None => FinalizeStage::Pending,
     };
-    let mut tickstamps: Vec<Tickstamp> = Vec::new();
+    let tickstamp_data = cache::fullzrange(run_id)?;
+    let start_time = cache::get(start_time_key(run_id))?
+        .ok_or_else(|| missing_run_key(run_id, "start time"))?;
+    let athlete_id = cache::get(athlete_key(run_id))?
+        .ok_or_else(|| missing_run_key(run_id, "athlete"))?;
+    let athlete = fetch_athlete(&athlete_id).await?.ok_or_else(|| {
+        ApiError::new(
+            ErrorCode::AthleteNotFound,
+            format!("no athlete with id {}", athlete_id),
+        )
+    })?;
+    let mut times: Vec<u64> = Vec::new();
+    let mut dropped_ticks = 0;
     for t in &tickstamp_data {
         let val = match t.parse() {
             Ok(t) => t,
-            Err(_) => continue,
+            Err(_) => {
+                dropped_ticks += 1;
+                continue;
+            }
         };
-        tickstamps.push(val);
+        times.push(val);
+    }
+    let base = timeline::base(
+        times.first().copied().unwrap_or_default(),
+        times.last().copied().unwrap_or_default(),
+    );
+    let tickstamps: Vec<Tickstamp> = times
+        .iter()
+        .filter_map(|t| Tickstamp::try_from(t - base).ok())
+        .collect();
+    dropped_ticks += times.len() - tickstamps.len();
+    // samples are only a refinement, so unreadable ones are left out
+    let clock_samples = cache::fullzrange(&clock_key(run_id))?
+        .iter()
+        .filter_map(|member| ClockSample::decode(member, base))
+        .collect();
+    let mut discontinuities = load_timeline(run_id)?.discontinuities;
+    for discontinuity in &mut discontinuities {
+        discontinuity.time = discontinuity.time.saturating_sub(base);
     }
 
     let raw_data = run::RawData {
         tickstamps,
         start_time,
+        clock_samples,
+        discontinuities,
     };
 
-    let data = raw_data.generate_json();
-
-    match write_data_to_s3(run_id, data).await {
-        Ok(_) => match cache::zrem(run_id) {
-            Ok(_) => match cache::del(&start_time_key(run_id)) {
-                Ok(_) => (),
-                Err(e) => {
-                    return FinalizeRunResponse::Error(format!(
-                        "failed to remove start key from cache: {}",
-                        e.msg
-                    ))
-                }
-            },
-            Err(e) => {
-                return FinalizeRunResponse::Error(format!(
-                    "failed to remove tickstamp data from cache: {}",
-                    e.msg
-                ))
-            }
-        },
+    let ingestion = IngestionMetadata {
+        finalized_at: now_millis(),
+        server_version: env!("CARGO_PKG_VERSION").to_string(),
+        dropped_ticks,
+        imported_from: None,
+    };
+    let config = config::get();
+    let data = Archive::new(run_id, &raw_data, &athlete, &config.analysis, ingestion).encode()?;
+    // the summary is recomputed on every attempt, the ticks stay cached until the last stage
+    let mut summary = match Summary::with_options(run_id, &athlete, raw_data, &config.analysis) {
+        Ok(summary) => summary,
         Err(e) => {
-            return FinalizeRunResponse::Error(format!("failed to write data to s3: {}", e.msg))
+            // runs rejected as too short are over all the same, so their ticks leave the cache
+            if config.runs.archive_short_runs {
+                write_data_to_s3(run_id, data).await?;
+            }
+            clear_run_cache(run_id)?;
+            return Err(e.into());
         }
+    };
+
+    // which records are new is decided once, since storing them makes them no longer new
+    if stage == FinalizeStage::Pending {
+        let (mut personal_records, _) = fetch_personal_records(&athlete.id).await?;
+        let new_personal_bests = personal_records.update(&summary);
+        let new_personal_bests = json::to_string(&new_personal_bests).map_err(|e| {
+            ApiError::new(ErrorCode::Internal, format!("error encoding personal bests: {}", e))
+        })?;
+        cache::set(personal_bests_key(run_id), new_personal_bests)?;
+        stage = advance(run_id, FinalizeStage::Computed)?;
     }
+    let new_personal_bests = cache::get(personal_bests_key(run_id))?
+        .ok_or_else(|| missing_run_key(run_id, "personal bests"))?;
+    summary.new_personal_bests = json::from_str(&new_personal_bests).map_err(|e| {
+        ApiError::new(ErrorCode::Internal, format!("error decoding personal bests: {}", e))
+    })?;
 
-    match Summary::new(run_id, raw_data) {
-        Ok(summary) => match push_summary_to_db(&summary).await {
-            Ok(()) => FinalizeRunResponse::Success(Json(summary)),
-            Err(e) => {
-                FinalizeRunResponse::Error(format!("failed to push summary to db: {}", e.msg))
-            }
-        },
-        Err(_) => FinalizeRunResponse::Error("failed to create summary of run".to_string()),
+    if stage < FinalizeStage::RawArchived {
+        write_data_to_s3(run_id, data).await?;
+        stage = advance(run_id, FinalizeStage::RawArchived)?;
     }
+
+    if stage < FinalizeStage::SummaryStored {
+        // folding the same run in twice leaves the records unchanged, so this may repeat
+        if !summary.new_personal_bests.is_empty() {
+            update_personal_records(&athlete.id, &summary).await?;
+        }
+        push_summary_to_db(&summary).await?;
+        advance(run_id, FinalizeStage::SummaryStored)?;
+    }
+
+    clear_run_cache(run_id)?;
+    Ok(summary)
 }
@@ -1,20 +1,30 @@
-use crate::constants::{
-    self, INTERVAL_SIZE, KILOMETERS_PER_MILE, MILLIS_PER_HOUR, SPEED_SMOOTHING, TICKS_PER_MILE,
+use crate::{
+    athlete::Athlete,
+    clock::{ClockSample, ClockSync},
+    constants::{
+        ADAPTIVE_DEBOUNCE, CLOCK_MAX_DRIFT_PPM, CLOCK_MIN_SPAN, DEBOUNCE_MAX_PERIOD,
+        DEBOUNCE_PERIOD_RATIO, DEBOUNCE_TIME, DEBOUNCE_WINDOW, INTERVAL_SIZE, MILLIS_PER_HOUR,
+        MIN_RUN_DISTANCE, MIN_RUN_TIME, TICKS_PER_MILE,
+    },
+    filter::SpeedFilterConfig,
+    records::RecordWindow,
+    timeline::{Discontinuity, Timeline},
 };
-use json::{object, JsonValue};
 use rocket::serde::Serialize;
 use serde::Deserialize;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 
 
 pub type Tickstamp = u32; // ms on device
-type Timestamp = u32; // time within run in seconds, since start
+pub type Timestamp = u32; // time within run in millis, since start
 type Speed = f32; // mph
 type Distance = f32; // distance in miles
 
 #[derive(Debug, PartialEq)]
 pub enum InvalidRunError {
     InsufficientData,
+    TooShort { total_time_secs: u32, min_time_secs: u32 },
+    TooLittleDistance { total_distance: f32, min_distance: f32 }, // in miles
 }
 
 #[derive(Debug)]
@@ -23,15 +33,46 @@ struct SpeedPoint {
     speed: f32,
 }
 pub struct RawData {
-    pub start_time: String,
+    pub start_time: String, // epoch millis when the run was started, at the server
     pub tickstamps: Vec<Tickstamp>,
+    pub clock_samples: Vec<ClockSample>,
+    pub discontinuities: Vec<Discontinuity>, // where the device counter started over
 }
 
-impl RawData {
-    pub fn generate_json(&self) -> JsonValue {
-        object! {
-            startTime: self.start_time.clone(),
-            ticks: self.tickstamps.clone()
+// how ticks are turned into a series and which runs are too short to keep; live
+// runs use the [analysis] table of the configuration
+#[derive(Debug, PartialEq, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct SummaryOptions {
+    pub ticks_per_mile: f32,
+    pub debounce_time: u32, // in millis
+    pub adaptive_debounce: bool,
+    pub debounce_period_ratio: f32,
+    pub debounce_window: usize,   // in ticks
+    pub debounce_max_period: u32, // in millis
+    pub interval_size: u32,       // in millis
+    pub speed_filter: SpeedFilterConfig,
+    pub min_run_time_secs: u32,
+    pub min_run_distance: f32, // in miles
+    pub clock_min_span: u32,   // in millis
+    pub clock_max_drift_ppm: f32,
+}
+
+impl Default for SummaryOptions {
+    fn default() -> SummaryOptions {
+        SummaryOptions {
+            ticks_per_mile: TICKS_PER_MILE,
+            debounce_time: DEBOUNCE_TIME,
+            adaptive_debounce: ADAPTIVE_DEBOUNCE,
+            debounce_period_ratio: DEBOUNCE_PERIOD_RATIO,
+            debounce_window: DEBOUNCE_WINDOW,
+            debounce_max_period: DEBOUNCE_MAX_PERIOD,
+            interval_size: INTERVAL_SIZE,
+            speed_filter: SpeedFilterConfig::default(),
+            min_run_time_secs: MIN_RUN_TIME,
+            min_run_distance: MIN_RUN_DISTANCE,
+            clock_min_span: CLOCK_MIN_SPAN,
+            clock_max_drift_ppm: CLOCK_MAX_DRIFT_PPM,
         }
     }
 }
@@ -49,9 +90,9 @@ pub struct LargestRect {
 }
 
 #[derive(Deserialize, Serialize, Debug, PartialEq)]
-pub struct DistanceRecordSet<'a>(#[serde(borrow)] pub HashMap<&'a str, Option<DistanceRecord>>);
-impl<'a> DistanceRecordSet<'a> {
-    fn new () -> DistanceRecordSet<'a> {
+pub struct DistanceRecordSet(pub HashMap<String, Option<DistanceRecord>>);
+impl DistanceRecordSet {
+    fn new () -> DistanceRecordSet {
         DistanceRecordSet(HashMap::new())
     }
 }
@@ -70,12 +111,29 @@ pub struct DistanceRecord {
     pub time: Timestamp,
 }
 
-#[derive(Debug, PartialEq)]
+// ticks that debouncing dropped, which are mostly switch bounces
+#[derive(Serialize, Deserialize, Debug, PartialEq, Default)]
+pub struct DebounceStats {
+    #[serde(rename = "droppedTicks")]
+    pub dropped_ticks: usize,
+    #[serde(rename = "bounceRate")]
+    pub bounce_rate: f32, // dropped ticks per tick posted
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct IntervalDatum {
+    #[serde(rename = "time")]
     pub time: Timestamp,
-    pub speed: Speed,
+    #[serde(rename = "speed")]
+    pub speed: Speed, // smoothed
+    #[serde(rename = "rawSpeed", default)]
+    pub raw_speed: Speed, // from the interval's ticks alone
+    #[serde(rename = "calories")]
     pub calories: f32,
+    #[serde(rename = "distance")]
     pub distance: Distance,
+    #[serde(rename = "wallTime", default)]
+    pub wall_time: u64, // epoch millis at the end of the interval
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
@@ -83,99 +141,298 @@ pub struct Summary<'a> {
     #[serde(rename = "startTime")]
     pub start_time: String, // epoch time when run started
     #[serde(rename = "bestDistances")]
-    pub distance_records: DistanceRecordSet<'a>,
+    pub distance_records: DistanceRecordSet,
     #[serde(rename = "totalTime")]
-    pub total_time: u32,
+    pub total_time_secs: u32,
+    // set when the device sent its clock, then the start time is the first tick's
+    #[serde(rename = "clockSync", default)]
+    pub clock_sync: Option<ClockSync>,
+    // where the device counter wrapped or restarted, stitched into one timeline
+    #[serde(rename = "discontinuities", default)]
+    pub discontinuities: Vec<Discontinuity>,
     #[serde(rename = "maxRectangle")]
     pub largest_rect: LargestRect,
     #[serde(rename = "runId")]
     pub id: &'a str,
+    #[serde(rename = "athleteId", default)]
+    pub athlete_id: String,
     #[serde(rename = "totalCalories")]
     pub total_calories: f32,
     #[serde(rename = "totalDistance")]
     pub total_distance: f32,
+    #[serde(rename = "newPersonalBests", default)]
+    pub new_personal_bests: HashMap<String, Vec<RecordWindow>>,
+    #[serde(rename = "debounce", default)]
+    pub debounce: DebounceStats,
     #[serde(skip)]
     pub interval_data: Vec<IntervalDatum>,
 }
 
+// the stored totals of a run, as listed in an athlete's history
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct RunListing {
+    #[serde(rename = "runId")]
+    pub id: String,
+    #[serde(rename = "startTime")]
+    pub start_time: String,
+    #[serde(rename = "totalTime")]
+    pub total_time_secs: u32,
+    #[serde(rename = "totalCalories")]
+    pub total_calories: f32,
+    #[serde(rename = "totalDistance")]
+    pub total_distance: f32,
+    #[serde(rename = "athleteId")]
+    pub athlete_id: String,
+    #[serde(rename = "bestDistances")]
+    pub distance_records: DistanceRecordSet,
+    #[serde(rename = "newPersonalBests")]
+    pub new_personal_bests: HashMap<String, Vec<RecordWindow>>,
+}
+
+impl RawData {
+    // gaps between counted ticks longer than debounce_max_period, from the tick
+    // before each to the one after, as read off the device
+    pub fn pauses(&self, options: &SummaryOptions) -> Vec<(Tickstamp, Tickstamp)> {
+        let first_tick = match self.tickstamps.first() {
+            Some(t) => *t,
+            None => return vec![],
+        };
+        let mut pauses = vec![];
+        let mut prev_tick = 0;
+        for tick in Summary::debounce_ticks(self, options) {
+            if tick - prev_tick > options.debounce_max_period {
+                pauses.push((first_tick + prev_tick, first_tick + tick));
+            }
+            prev_tick = tick;
+        }
+        pauses
+    }
+}
+
 impl Summary<'_> {
-    pub fn new(id: &str, raw_data: RawData) -> Result<Summary, InvalidRunError> {
-        let start_time = raw_data.start_time.clone();
-        let id = id;
-        let interval_data = Summary::calculate_interval_data(&raw_data, INTERVAL_SIZE);
-        let total_time = Summary::calculate_total_time(&raw_data)?;
-
-        let record_distances: HashMap<&'static str, f32> = HashMap::from([
-            ("oneMile", 1.),
-            ("fiveKm", 5. * KILOMETERS_PER_MILE),
-            ("fiveMiles", 5.),
-            ("fourMiles", 4.),
-            ("halfMile", 0.5),
-            ("lap", 0.25),
-            ("oneKm", KILOMETERS_PER_MILE),
-            ("tenKm", 10. * KILOMETERS_PER_MILE),
-            ("threeMiles", 3.),
-            ("twoMiles", 2.),
-        ]);
+    pub fn new<'a>(
+        id: &'a str,
+        athlete: &Athlete,
+        raw_data: RawData,
+    ) -> Result<Summary<'a>, InvalidRunError> {
+        Summary::with_options(id, athlete, raw_data, &SummaryOptions::default())
+    }
+
+    pub fn with_options<'a>(
+        id: &'a str,
+        athlete: &Athlete,
+        raw_data: RawData,
+        options: &SummaryOptions,
+    ) -> Result<Summary<'a>, InvalidRunError> {
+        let total_time_secs = Summary::calculate_total_time(&raw_data)?;
+        let first_tick = raw_data.tickstamps.first().copied().unwrap_or_default();
+        // a restart loses an unknown stretch of device time, so later samples don't fit
+        let clock_samples: Vec<ClockSample> = match Timeline::first_reset(&raw_data.discontinuities) {
+            Some(reset) => raw_data
+                .clock_samples
+                .iter()
+                .filter(|s| (s.device_time as u64) < reset)
+                .copied()
+                .collect(),
+            None => raw_data.clock_samples.clone(),
+        };
+        let clock_sync = ClockSync::estimate(&clock_samples, first_tick, options);
+        let start_time = match clock_sync {
+            Some(clock) => clock.start.to_string(),
+            None => raw_data.start_time.clone(),
+        };
+        if total_time_secs < options.min_run_time_secs {
+            return Err(InvalidRunError::TooShort {
+                total_time_secs,
+                min_time_secs: options.min_run_time_secs,
+            });
+        }
+        let mut interval_data =
+            Summary::calculate_interval_data(&raw_data, options, athlete.weight);
+        if interval_data.is_empty() {
+            return Err(InvalidRunError::InsufficientData);
+        }
+        let total_distance = Summary::calculate_total_distance(&interval_data);
+        if total_distance < options.min_run_distance {
+            return Err(InvalidRunError::TooLittleDistance {
+                total_distance,
+                min_distance: options.min_run_distance,
+            });
+        }
+
+        let start = start_time.parse().unwrap_or_default();
+        for datum in &mut interval_data {
+            datum.wall_time = Summary::to_wall_time(start, clock_sync.as_ref(), datum.time);
+        }
+
         let distance_records =
-            Summary::calculate_distance_records(&interval_data, record_distances);
+            Summary::calculate_distance_records(&interval_data, &athlete.record_distances);
         let largest_rect = Summary::calculate_largest_rect(&interval_data);
         let total_calories = Summary::calculate_total_calories(&interval_data);
-        let total_distance = Summary::calculate_total_distance(&interval_data);
+        let debounce = Summary::calculate_debounce_stats(&raw_data, options);
+        let discontinuities = raw_data
+            .discontinuities
+            .iter()
+            .map(|d| Discontinuity {
+                time: d.time.saturating_sub(first_tick as u64),
+                ..*d
+            })
+            .collect();
         Ok(Summary {
             start_time,
-            total_time,
+            total_time_secs,
+            clock_sync,
+            discontinuities,
             distance_records,
             id,
+            athlete_id: athlete.id.clone(),
             total_calories,
             largest_rect,
             total_distance,
+            new_personal_bests: HashMap::new(),
+            debounce,
             interval_data,
         })
     }
 
-    fn calculate_interval_data(raw_data: &RawData, interval_length: u32) -> Vec<IntervalDatum> {
-        let debounced_ticks = Summary::debounce(&raw_data);
-        let mut res = vec![];
-        let mut second: u32 = 1;
+    fn to_wall_time(start: u64, clock_sync: Option<&ClockSync>, time: Timestamp) -> u64 {
+        match clock_sync {
+            Some(clock) => clock.wall_time(time),
+            None => start + time as u64,
+        }
+    }
+
+    // epoch millis at time into the run
+    pub fn wall_time(&self, time: Timestamp) -> u64 {
+        let start = self.start_time.parse().unwrap_or_default();
+        Summary::to_wall_time(start, self.clock_sync.as_ref(), time)
+    }
+
+    fn calculate_interval_data(
+        raw_data: &RawData,
+        options: &SummaryOptions,
+        weight: f32,
+    ) -> Vec<IntervalDatum> {
+        let interval_length = options.interval_size;
+        let debounced_ticks = Summary::debounce_ticks(raw_data, options);
+        let mut interval_ends = vec![];
+        let mut raw_speeds = vec![];
+        let mut distances = vec![];
+        let mut interval: u32 = 1;
         let mut i: usize = 0;
-        let mut speed = 0.0;
 
         let last_tick = match debounced_ticks.last() {
             Some(t) => t,
             None => return vec![],
         };
 
-        while interval_length * second < *last_tick {
+        loop {
+            // stops short of overflowing on runs that end near the counter's limit
+            let interval_end = match interval_length.checked_mul(interval) {
+                Some(end) if end < *last_tick => end,
+                _ => break,
+            };
             let window_begin = i;
-            while debounced_ticks
-                .get(i)
-                .expect("somehow i got out of range here")
-                < &(interval_length * second)
-            {
+            // ticks are increasing and the last is past interval_end, so i stays in range
+            while debounced_ticks[i] < interval_end {
                 i += 1;
             }
-            let ticks_per_millis = ((i - window_begin) as f32)
-                / ((debounced_ticks[i] - debounced_ticks[window_begin]) as f32);
-
-            let immediate_speed = ticks_per_millis * MILLIS_PER_HOUR as f32 / TICKS_PER_MILE;
-            speed = immediate_speed * (1. - SPEED_SMOOTHING) + SPEED_SMOOTHING * speed;
-            let incline = 1.0;
-            let weight = 192.0;
-            let calories =
-                (1.0 / 60 as f32) * (weight / 26400.) * (speed * (322. + 14.5 * incline) + 210.);
-            res.push(IntervalDatum {
-                time: second,
+            // an interval without ticks is a pause, not 0 / 0
+            let ticks_per_millis = match i - window_begin {
+                0 => 0.,
+                ticks => {
+                    ticks as f32 / ((debounced_ticks[i] - debounced_ticks[window_begin]) as f32)
+                }
+            };
+
+            interval_ends.push(interval_end);
+            raw_speeds.push(ticks_per_millis * MILLIS_PER_HOUR as f32 / options.ticks_per_mile);
+            distances.push(i as f32 / options.ticks_per_mile);
+            interval += 1;
+        }
+
+        let speeds = options.speed_filter.filter().smooth(&raw_speeds);
+        let incline = 1.0;
+        // the calorie formula is per second
+        let seconds = interval_length as f32 / 1000.;
+        interval_ends
+            .into_iter()
+            .zip(speeds)
+            .zip(raw_speeds)
+            .zip(distances)
+            .map(|(((time, speed), raw_speed), distance)| IntervalDatum {
+                time,
                 speed,
-                calories,
-                distance: i as f32 / TICKS_PER_MILE,
-            });
-            second += 1;
+                raw_speed,
+                calories: seconds
+                    * (1.0 / 60.0)
+                    * (weight / 26400.)
+                    * (speed * (322. + 14.5 * incline) + 210.),
+                distance,
+                wall_time: 0, // known once the start is
+            })
+            .collect()
+    }
+
+    fn debounce_ticks(raw_data: &RawData, options: &SummaryOptions) -> Vec<Tickstamp> {
+        match options.adaptive_debounce {
+            true => Summary::debounce_adaptive(raw_data, options),
+            false => Summary::debounce(raw_data, options.debounce_time),
         }
-        res
     }
 
-    fn debounce(raw_data: &RawData) -> Vec<Tickstamp> {
+    // like debounce, but also drops ticks that come much sooner than the pace of
+    // the ticks before them, which catches bounces at walking pace that outlast
+    // debounce_time
+    fn debounce_adaptive(raw_data: &RawData, options: &SummaryOptions) -> Vec<Tickstamp> {
+        let debounce_time = options.debounce_time;
+        let mut ticks = vec![];
+        let first_tick = match raw_data.tickstamps.first() {
+            Some(t) => t,
+            None => return ticks,
+        };
+        let mut prev_tick = 0;
+        let mut periods: VecDeque<u32> = VecDeque::with_capacity(options.debounce_window + 1);
+        for tick in raw_data.tickstamps.iter().skip(1) {
+            let this_tick = tick.saturating_sub(*first_tick);
+            let gap = this_tick.saturating_sub(prev_tick);
+            // the median is not thrown off by the odd bounce that got through
+            let mut sorted: Vec<u32> = periods.iter().copied().collect();
+            sorted.sort_unstable();
+            let threshold = match sorted.get(sorted.len() / 2) {
+                Some(period) => {
+                    debounce_time.max((*period as f32 * options.debounce_period_ratio) as u32)
+                }
+                None => debounce_time,
+            };
+            if gap > threshold {
+                ticks.push(this_tick);
+                prev_tick = this_tick;
+                if gap <= options.debounce_max_period {
+                    periods.push_back(gap);
+                    if periods.len() > options.debounce_window {
+                        periods.pop_front();
+                    }
+                }
+            }
+        }
+        ticks
+    }
+
+    fn calculate_debounce_stats(raw_data: &RawData, options: &SummaryOptions) -> DebounceStats {
+        // the first tick marks the start and is never counted
+        let posted = raw_data.tickstamps.len().saturating_sub(1);
+        let dropped_ticks = posted - Summary::debounce_ticks(raw_data, options).len();
+        DebounceStats {
+            dropped_ticks,
+            bounce_rate: match posted {
+                0 => 0.,
+                _ => dropped_ticks as f32 / posted as f32,
+            },
+        }
+    }
+
+    fn debounce(raw_data: &RawData, debounce_time: u32) -> Vec<Tickstamp> {
         let mut ticks = vec![];
         let mut prev_tick = 0; // value doesn't matter will be overwritten on first iteration
         let first_tick = match raw_data.tickstamps.first() {
@@ -186,8 +443,9 @@ impl Summary<'_> {
             if i == 0 {
                 prev_tick = 0;
             } else {
-                let this_tick = tick - first_tick;
-                if this_tick - prev_tick > constants::DEBOUNCE_TIME {
+                // out of order ticks saturate to at most prev_tick and are dropped
+                let this_tick = tick.saturating_sub(*first_tick);
+                if this_tick.saturating_sub(prev_tick) > debounce_time {
                     ticks.push(this_tick);
                     prev_tick = this_tick;
                 }
@@ -197,9 +455,13 @@ impl Summary<'_> {
     }
 
     fn calculate_distance_record(
-        data: &Vec<IntervalDatum>,
+        data: &[IntervalDatum],
         distance: f32,
     ) -> Option<DistanceRecord> {
+        // NaN and non-positive distances would let left pass right
+        if distance.is_nan() || distance <= 0. {
+            return None;
+        }
         let mut left: usize = 0;
         let mut right: usize = 0;
         let mut bests: Option<DistanceRecord> = None;
@@ -238,13 +500,13 @@ impl Summary<'_> {
         bests
     }
 
-    fn calculate_distance_records<'a>(
-        data: &Vec<IntervalDatum>,
-        record_distances: HashMap<&'a str, f32>,
-    ) -> DistanceRecordSet<'a> {
+    fn calculate_distance_records(
+        data: &[IntervalDatum],
+        record_distances: &HashMap<String, f32>,
+    ) -> DistanceRecordSet {
         let mut res = DistanceRecordSet::new();
         for (name, distance) in record_distances {
-            res.0.insert(name, Summary::calculate_distance_record(data, distance));
+            res.0.insert(name.to_string(), Summary::calculate_distance_record(data, *distance));
         }
         res
     }
@@ -258,38 +520,47 @@ impl Summary<'_> {
             .tickstamps
             .last()
             .ok_or(InvalidRunError::InsufficientData)?;
-        Ok((last - first) / 1000)
+        Ok(last.saturating_sub(*first) / 1000)
     }
 
-    fn calculate_total_calories(data: &Vec<IntervalDatum>) -> f32 {
+    fn calculate_total_calories(data: &[IntervalDatum]) -> f32 {
         data.iter().map(|d| d.calories).sum()
     }
 
-    fn calculate_total_distance(data: &Vec<IntervalDatum>) -> f32 {
+    fn calculate_total_distance(data: &[IntervalDatum]) -> f32 {
         match data.last() {
             Some(d) => d.distance,
             None => 0.,
         }
     }
 
-    fn calculate_largest_rect(data: &Vec<IntervalDatum>) -> LargestRect {
-        let mut max_area_rect = LargestRect {
-            start_time: data[0].time - 1,
-            end_time: data[0].time,
-            height: data[0].speed,
-            area: data[0].speed,
+    // areas are in mph times seconds, whatever the interval size
+    fn calculate_largest_rect(data: &[IntervalDatum]) -> LargestRect {
+        let mut max_area_rect = match data.first() {
+            Some(d) => LargestRect {
+                start_time: 0,
+                end_time: d.time,
+                height: d.speed,
+                area: d.speed * d.time as f32 / 1000.,
+            },
+            None => LargestRect {
+                start_time: 0,
+                end_time: 0,
+                height: 0.,
+                area: 0.,
+            },
         };
         let mut stack: Vec<SpeedPoint> = Vec::new();
 
         for (i, d) in data.iter().enumerate() {
-            while stack.len() > 0 && (i == data.len() || stack.last().unwrap().speed >= d.speed) {
+            while !stack.is_empty() && (i == data.len() || stack.last().unwrap().speed >= d.speed) {
                 let popped_bar = stack.pop().unwrap();
-                let left_time = if stack.len() == 0 {
+                let left_time = if stack.is_empty() {
                     0
                 } else {
                     stack.last().unwrap().time
                 };
-                let area = popped_bar.speed * (d.time - left_time) as f32;
+                let area = popped_bar.speed * (d.time - left_time) as f32 / 1000.;
                 if area > max_area_rect.area {
                     max_area_rect = LargestRect {
                         start_time: left_time,
@@ -311,63 +582,127 @@ impl Summary<'_> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{constants::MAX_TICK_REWIND, test_fixtures::athlete, timeline::DiscontinuityKind};
 
     #[test]
     fn calculate_largest_rect_success() {
         let data = vec![
             IntervalDatum {
-                time: 1,
+                time: 1000,
                 speed: 1.0,
+                raw_speed: 1.0,
                 calories: 0.,
                 distance: 1.,
+                wall_time: 0,
             },
             IntervalDatum {
-                time: 2,
+                time: 2000,
                 speed: 2.0,
+                raw_speed: 2.0,
                 calories: 0.,
                 distance: 1.,
+                wall_time: 0,
             },
             IntervalDatum {
-                time: 3,
+                time: 3000,
                 speed: 3.0,
+                raw_speed: 3.0,
                 calories: 0.,
                 distance: 1.,
+                wall_time: 0,
             },
             IntervalDatum {
-                time: 4,
+                time: 4000,
                 speed: 4.0,
+                raw_speed: 4.0,
                 calories: 0.,
                 distance: 1.,
+                wall_time: 0,
             },
             IntervalDatum {
-                time: 5,
+                time: 5000,
                 speed: 5.0,
+                raw_speed: 5.0,
                 calories: 0.,
                 distance: 1.,
+                wall_time: 0,
             },
             IntervalDatum {
-                time: 5,
+                time: 5000,
                 speed: 0.0,
+                raw_speed: 0.0,
                 calories: 0.,
                 distance: 1.,
+                wall_time: 0,
             },
         ];
         let lr = Summary::calculate_largest_rect(&data);
         assert_eq!(
             lr,
             LargestRect {
-                start_time: 2,
-                end_time: 5,
+                start_time: 2000,
+                end_time: 5000,
                 height: 3.,
                 area: 9.
             }
         )
     }
+    #[test]
+    fn calculate_largest_rect_empty() {
+        let lr = Summary::calculate_largest_rect(&[]);
+        assert_eq!(lr.area, 0.);
+    }
+
+    #[test]
+    fn new_summary_too_short() {
+        let rd = RawData {
+            start_time: "123456".to_string(),
+            tickstamps: (1..100).map(|e| 40 * e).collect(),
+            clock_samples: vec![],
+            discontinuities: vec![],
+        };
+        let err = Summary::new("run", &athlete(), rd).unwrap_err();
+        assert_eq!(
+            err,
+            InvalidRunError::TooShort {
+                total_time_secs: 3,
+                min_time_secs: MIN_RUN_TIME
+            }
+        );
+    }
+
+    #[test]
+    fn new_summary_too_little_distance() {
+        let rd = RawData {
+            start_time: "123456".to_string(),
+            tickstamps: vec![100, 5_000, 30_000],
+            clock_samples: vec![],
+            discontinuities: vec![],
+        };
+        let err = Summary::new("run", &athlete(), rd).unwrap_err();
+        assert!(matches!(err, InvalidRunError::TooLittleDistance { .. }));
+    }
+
+    #[test]
+    fn new_summary_success() {
+        let rd = RawData {
+            start_time: "123456".to_string(),
+            tickstamps: (1..1000).map(|e| 40 * e).collect(),
+            clock_samples: vec![],
+            discontinuities: vec![],
+        };
+        let summary = Summary::new("run", &athlete(), rd).unwrap();
+        assert_eq!(summary.total_time_secs, 39);
+        assert_eq!(summary.athlete_id, "athlete");
+    }
+
     #[test]
     fn calculate_total_time_fail() {
         let rd = RawData {
             start_time: "123456".to_string(),
             tickstamps: vec![],
+            clock_samples: vec![],
+            discontinuities: vec![],
         };
         let tt = Summary::calculate_total_time(&rd);
         assert_eq!(tt.unwrap_err(), InvalidRunError::InsufficientData);
@@ -378,6 +713,8 @@ mod tests {
         let rd = RawData {
             start_time: "123456".to_string(),
             tickstamps: vec![12123, 19456],
+            clock_samples: vec![],
+            discontinuities: vec![],
         };
         let tt = Summary::calculate_total_time(&rd);
         assert_eq!(tt.unwrap(), 7);
@@ -388,27 +725,307 @@ mod tests {
         let rd = RawData {
             start_time: "123456".to_string(),
             tickstamps: vec![6, 19, 40, 100],
+            clock_samples: vec![],
+            discontinuities: vec![],
         };
-        let db = Summary::debounce(&rd);
+        let db = Summary::debounce(&rd, DEBOUNCE_TIME);
         assert_eq!(db, vec![34, 94]);
     }
 
+    #[test]
+    fn debounce_adaptive_walking_pace() {
+        // about 2 mph, bouncing for longer than DEBOUNCE_TIME on every other tick
+        // once the pace is known
+        let mut tickstamps = vec![];
+        for e in 0..200 {
+            tickstamps.push(180 * e);
+            if e >= 10 && e % 2 == 0 {
+                tickstamps.push(180 * e + 45);
+            }
+        }
+        let rd = RawData {
+            start_time: "123456".to_string(),
+            tickstamps,
+            clock_samples: vec![],
+            discontinuities: vec![],
+        };
+        assert_eq!(Summary::debounce(&rd, DEBOUNCE_TIME).len(), 294);
+        assert_eq!(Summary::debounce_adaptive(&rd, &SummaryOptions::default()).len(), 199);
+
+        let stats = Summary::calculate_debounce_stats(&rd, &SummaryOptions::default());
+        assert_eq!(stats.dropped_ticks, 95);
+        assert_eq!(stats.bounce_rate, 95. / 294.);
+    }
+
+    #[test]
+    fn debounce_adaptive_keeps_fast_ticks() {
+        // about 13 mph with a little jitter, close to DEBOUNCE_TIME
+        let tickstamps: Vec<Tickstamp> = (0..500).map(|e| 27 * e + e % 3).collect();
+        let rd = RawData {
+            start_time: "123456".to_string(),
+            tickstamps,
+            clock_samples: vec![],
+            discontinuities: vec![],
+        };
+        assert_eq!(Summary::debounce_adaptive(&rd, &SummaryOptions::default()).len(), 499);
+        // a pause does not make the ticks after it look like bounces
+        let mut tickstamps = rd.tickstamps.clone();
+        tickstamps.extend((0..100).map(|e| 60_000 + 27 * e));
+        let rd = RawData {
+            start_time: "123456".to_string(),
+            tickstamps,
+            clock_samples: vec![],
+            discontinuities: vec![],
+        };
+        assert_eq!(Summary::debounce_adaptive(&rd, &SummaryOptions::default()).len(), 599);
+    }
+
     #[test]
     fn calculate_interval_data_success() {
         let rd = RawData {
             start_time: "123456".to_string(),
             tickstamps: (1..100).map(|e| 40 * e).collect(),
+            clock_samples: vec![],
+            discontinuities: vec![],
         };
-        let id = Summary::calculate_interval_data(&rd, 1000);
+        let id = Summary::calculate_interval_data(&rd, &SummaryOptions::default(), 192.0);
         assert_eq!(id.len(), 3);
         assert_eq!(
             id[0],
             IntervalDatum {
-                time: 1,
+                time: 1000,
                 speed: 4.431818,
+                raw_speed: 8.863636,
                 calories: 0.20621902,
-                distance: 0.0023636362
+                distance: 0.0023636362,
+                wall_time: 0,
             }
         );
     }
+
+    #[test]
+    fn calculate_interval_data_resolution() {
+        let rd = RawData {
+            start_time: "123456".to_string(),
+            tickstamps: (1..100).map(|e| 40 * e).collect(),
+            clock_samples: vec![],
+            discontinuities: vec![],
+        };
+        // unsmoothed, so that speeds don't depend on how many intervals came before
+        let options = SummaryOptions {
+            speed_filter: SpeedFilterConfig::Ema { smoothing: 0. },
+            ..Default::default()
+        };
+        let seconds = Summary::calculate_interval_data(&rd, &options, 192.0);
+        let options = SummaryOptions {
+            interval_size: 250,
+            ..options
+        };
+        let quarters = Summary::calculate_interval_data(&rd, &options, 192.0);
+        assert_eq!(quarters.len(), 15);
+        assert_eq!(quarters[3].time, 1000);
+        assert_eq!(quarters[3].speed, seconds[0].speed);
+        assert_eq!(quarters[3].distance, seconds[0].distance);
+        // calories are per interval, so four quarters burn what a second does
+        assert!((quarters[3].calories * 4. - seconds[0].calories).abs() < 1e-6);
+    }
+
+    #[test]
+    fn calculate_interval_data_pause() {
+        // 10 s at about 6 mph, 5 s standing still, then running again
+        let mut tickstamps: Vec<Tickstamp> = (0..170).map(|e| 60 * e).collect();
+        tickstamps.extend((0..170).map(|e| 15_000 + 60 * e));
+        let rd = RawData {
+            start_time: "123456".to_string(),
+            tickstamps,
+            clock_samples: vec![],
+            discontinuities: vec![],
+        };
+        let id = Summary::calculate_interval_data(&rd, &SummaryOptions::default(), 192.0);
+        assert!(id.iter().all(|d| d.speed.is_finite() && d.calories.is_finite()));
+        // the smoothed speed halves with every interval without ticks
+        assert!(id[13].speed < 1.);
+        assert!(id[id.len() - 1].speed > 5.);
+    }
+
+    #[test]
+    fn summary_across_counter_reset() {
+        // a minute at about 6 mph, a brownout, then another minute
+        let readings = (0..300)
+            .map(|i| 4_000_000 + 200 * i)
+            .chain((0..300).map(|i| 3000 + 200 * i));
+        let mut timeline = Timeline::default();
+        let tickstamps = readings
+            .map(|r| timeline.stitch(r, MAX_TICK_REWIND) as Tickstamp)
+            .collect();
+        let rd = RawData {
+            start_time: "1656202584971".to_string(),
+            tickstamps,
+            clock_samples: vec![
+                ClockSample {
+                    device_time: 4_000_000,
+                    wall_time: 1656202584971,
+                },
+                ClockSample {
+                    device_time: 4_062_800,
+                    wall_time: 1656202584971 + 66_800,
+                },
+            ],
+            discontinuities: timeline.discontinuities,
+        };
+        let summary = Summary::new("run", &athlete(), rd).unwrap();
+        assert_eq!(summary.total_time_secs, 122);
+        assert_eq!(
+            summary.discontinuities,
+            vec![Discontinuity {
+                kind: DiscontinuityKind::Reset,
+                time: 62_800,
+            }]
+        );
+        // the sample after the restart doesn't count
+        assert_eq!(summary.clock_sync.unwrap().samples, 1);
+        assert!(summary.interval_data.iter().all(|d| d.raw_speed < 7.));
+    }
+
+    #[test]
+    fn calculate_interval_data_near_counter_limit() {
+        let rd = RawData {
+            start_time: "123456".to_string(),
+            tickstamps: vec![0, 1000, Tickstamp::MAX - 1000, Tickstamp::MAX],
+            clock_samples: vec![],
+            discontinuities: vec![],
+        };
+        let id = Summary::calculate_interval_data(&rd, &SummaryOptions::default(), 192.0);
+        assert_eq!(id.len(), (Tickstamp::MAX / 1000) as usize);
+    }
+
+    mod properties {
+        use super::*;
+        use proptest::{collection::vec, prelude::*};
+
+        // sorted tickstamps as a device posts them: mostly steady running, some
+        // bounces and the odd pause
+        fn tick_stream(min_gap: u32) -> impl Strategy<Value = Vec<Tickstamp>> {
+            (
+                0..1_000_000_000u32,
+                vec(
+                    prop_oneof![
+                        8 => min_gap..min_gap + 200,
+                        1 => 0..min_gap + 1,
+                        1 => min_gap..30_000,
+                    ],
+                    0..2000,
+                ),
+            )
+                .prop_map(|(start, gaps)| {
+                    gaps.iter()
+                        .scan(start, |t, gap| {
+                            *t += gap;
+                            Some(*t)
+                        })
+                        .collect()
+                })
+        }
+
+        fn raw_data(tickstamps: Vec<Tickstamp>) -> RawData {
+            RawData {
+                start_time: "123456".to_string(),
+                tickstamps,
+                clock_samples: vec![],
+                discontinuities: vec![],
+            }
+        }
+
+        proptest! {
+            #[test]
+            fn debounced_ticks_are_spaced(
+                ticks in vec(0..20_000_000u32, 0..500),
+                debounce_time in 0..100u32,
+            ) {
+                let rd = raw_data(ticks.clone());
+                let fixed = Summary::debounce(&rd, debounce_time);
+                let options = SummaryOptions { debounce_time, ..Default::default() };
+                let adaptive = Summary::debounce_adaptive(&rd, &options);
+                prop_assert!(adaptive.len() <= fixed.len());
+                for debounced in [fixed, adaptive] {
+                    prop_assert!(debounced.len() <= ticks.len().saturating_sub(1));
+                    let mut prev = 0;
+                    for t in debounced {
+                        prop_assert!(t - prev > debounce_time);
+                        prev = t;
+                    }
+                }
+            }
+
+            #[test]
+            fn interval_data_is_consistent(
+                ticks in tick_stream(20),
+                ticks_per_mile in 1000f32..20000.,
+                interval_size in 100..5000u32,
+            ) {
+                let options = SummaryOptions { ticks_per_mile, interval_size, ..Default::default() };
+                let rd = raw_data(ticks);
+                let data = Summary::calculate_interval_data(&rd, &options, 192.);
+                let mut prev_distance = 0.;
+                for (i, d) in data.iter().enumerate() {
+                    prop_assert_eq!(d.time as usize, (i + 1) * interval_size as usize);
+                    prop_assert!(d.distance >= prev_distance);
+                    prop_assert!(d.speed.is_finite() && d.speed >= 0.);
+                    prop_assert!(d.calories.is_finite() && d.calories >= 0.);
+                    prev_distance = d.distance;
+                }
+                // every debounced tick before the end of the last interval counts once
+                let end = options.interval_size * data.len() as u32;
+                let counted = Summary::debounce_ticks(&rd, &options)
+                    .iter()
+                    .filter(|t| **t < end)
+                    .count();
+                prop_assert_eq!(
+                    Summary::calculate_total_distance(&data),
+                    counted as f32 / ticks_per_mile
+                );
+            }
+
+            #[test]
+            fn records_are_physically_possible(
+                (min_gap, ticks) in (30..500u32).prop_flat_map(|g| (Just(g), tick_stream(g))),
+                distance in 0.01f32..1.,
+            ) {
+                // only gaps of at least min_gap survive debouncing
+                let options = SummaryOptions { debounce_time: min_gap, ..Default::default() };
+                let rd = raw_data(ticks);
+                let data = Summary::calculate_interval_data(&rd, &options, 192.);
+                let max_speed = MILLIS_PER_HOUR as f32 / (min_gap as f32 * options.ticks_per_mile);
+                prop_assert!(data.iter().all(|d| d.speed <= max_speed * 1.001));
+                if let Some(record) = Summary::calculate_distance_record(&data, distance) {
+                    let covered = record.end_distance - record.start_distance;
+                    prop_assert!(covered > distance);
+                    prop_assert!(record.time >= options.interval_size);
+                    // at most one tick every min_gap, plus one at the window's edge
+                    let max_ticks = (record.time / min_gap + 1) as f32;
+                    prop_assert!(covered * options.ticks_per_mile <= max_ticks + 0.01);
+                }
+            }
+
+            #[test]
+            fn summaries_never_panic(
+                ticks in vec(0..20_000_000u32, 0..300),
+                sorted in any::<bool>(),
+                weight in 1f32..500.,
+                record_distance in any::<f32>(),
+            ) {
+                let mut ticks = ticks;
+                if sorted {
+                    ticks.sort();
+                }
+                let mut athlete = athlete();
+                athlete.weight = weight;
+                athlete.record_distances.insert("any".to_string(), record_distance);
+                if let Ok(summary) = Summary::new("run", &athlete, raw_data(ticks)) {
+                    prop_assert!(summary.total_distance.is_finite());
+                    prop_assert!(summary.total_calories.is_finite());
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,362 @@
+use std::time::Duration;
+
+use crate::{
+    constants::{DEBOUNCE_TIME, MILLIS_PER_HOUR, TICKS_PER_MILE},
+    run::Tickstamp,
+};
+
+// splitmix64, so that a seed reproduces a simulated run exactly
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+
+    // uniform in [0, 1)
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    pub fn chance(&mut self, probability: f64) -> bool {
+        self.next_f64() < probability
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Segment {
+    pub duration: u32, // in seconds
+    pub speed: f32,    // in mph, 0 for a pause
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Workout(pub Vec<Segment>);
+
+impl Workout {
+    // "<seconds>@<mph>,..." e.g. "300@5,60@9,30@0"
+    pub fn parse(spec: &str) -> Option<Workout> {
+        let segments = spec
+            .split(',')
+            .map(|s| {
+                let (duration, speed) = s.trim().split_once('@')?;
+                let speed: f32 = speed.parse().ok()?;
+                match speed >= 0. {
+                    true => Some(Segment {
+                        duration: duration.parse().ok()?,
+                        speed,
+                    }),
+                    false => None,
+                }
+            })
+            .collect::<Option<Vec<Segment>>>()?;
+        Some(Workout(segments))
+    }
+
+    pub fn preset(name: &str) -> Option<Workout> {
+        match name {
+            "steady" => Workout::parse("1800@6"),
+            // warm-up, six intervals, a pause and a cool-down
+            "intervals" => Workout::parse(
+                "300@5,60@9,90@5.5,60@9,90@5.5,60@9,90@5.5,60@0,60@9,90@5.5,60@9,90@5.5,60@9,90@5.5,300@4.5",
+            ),
+            "short" => Workout::parse("60@6"),
+            _ => None,
+        }
+    }
+
+    pub fn duration(&self) -> u32 {
+        self.0.iter().map(|s| s.duration).sum()
+    }
+
+    // in miles
+    pub fn distance(&self) -> f32 {
+        self.0
+            .iter()
+            .map(|s| s.duration as f32 * s.speed / 3600.)
+            .sum()
+    }
+}
+
+pub struct SimulationOptions {
+    pub jitter: f64,         // relative variation of the time between ticks
+    pub bounce: f64, // chance that the tick switch bounces, adding a tick within debounce time
+    pub drift_ppm: f64, // how much faster (or, negative, slower) the device clock runs
+    pub drop_batch: f64, // chance that a posted batch never arrives
+    pub batch_interval: u32, // in millis of real time between posts
+    pub seed: u64,
+}
+
+impl Default for SimulationOptions {
+    fn default() -> SimulationOptions {
+        SimulationOptions {
+            jitter: 0.02,
+            bounce: 0.01,
+            drift_ppm: 50.,
+            drop_batch: 0.,
+            batch_interval: 1000,
+            seed: 1,
+        }
+    }
+}
+
+// the ticks a device would post at send_at millis into the run, when its own
+// clock reads device_time
+#[derive(Debug, PartialEq)]
+pub struct Batch {
+    pub send_at: u64,
+    pub device_time: Tickstamp,
+    pub ticks: Vec<Tickstamp>,
+}
+
+#[derive(Debug)]
+pub struct SimulatedRun {
+    pub device_start: Tickstamp, // the device clock when the run starts
+    pub batches: Vec<Batch>,
+    pub dropped_batches: usize,
+    pub bounced_ticks: usize,
+}
+
+// tickstamps are read off the device's millis counter, which started some time
+// before the run and may drift from real time; the first tick marks the start
+pub fn simulate(workout: &Workout, options: &SimulationOptions) -> SimulatedRun {
+    let mut rng = Rng::new(options.seed);
+    let mut times: Vec<f64> = vec![0.]; // real millis since start
+    let mut bounced_ticks = 0;
+    let mut t = 0.;
+    let mut segment_start = 0.;
+    for segment in &workout.0 {
+        let segment_end = segment_start + segment.duration as f64 * 1000.;
+        if segment.speed > 0. {
+            let interval = MILLIS_PER_HOUR as f64 / (segment.speed as f64 * TICKS_PER_MILE as f64);
+            t = f64::max(t, segment_start);
+            loop {
+                let next = t + interval * (1. + options.jitter * (2. * rng.next_f64() - 1.));
+                if next > segment_end {
+                    break;
+                }
+                t = next;
+                times.push(t);
+                if rng.chance(options.bounce) {
+                    times.push(t + rng.next_f64() * DEBOUNCE_TIME as f64 * 0.8);
+                    bounced_ticks += 1;
+                }
+            }
+        }
+        segment_start = segment_end;
+    }
+    times.sort_by(f64::total_cmp);
+
+    let boot = 1_000_000. + rng.next_f64() * 1_000_000_000.;
+    let drift = 1. + options.drift_ppm / 1_000_000.;
+    let batch_interval = options.batch_interval.max(1) as f64;
+    let mut batches: Vec<Batch> = vec![];
+    let mut dropped_batches = 0;
+    let mut pending: Vec<Tickstamp> = vec![];
+    let mut batch_end = batch_interval;
+    let mut flush = |pending: &mut Vec<Tickstamp>, send_at: f64, rng: &mut Rng| {
+        if pending.is_empty() {
+            return;
+        }
+        let ticks = std::mem::take(pending);
+        match rng.chance(options.drop_batch) {
+            true => dropped_batches += 1,
+            false => batches.push(Batch {
+                send_at: send_at as u64,
+                device_time: (boot + send_at * drift) as Tickstamp,
+                ticks,
+            }),
+        }
+    };
+    for time in times {
+        while time >= batch_end {
+            flush(&mut pending, batch_end, &mut rng);
+            batch_end += batch_interval;
+        }
+        pending.push((boot + time * drift) as Tickstamp);
+    }
+    flush(&mut pending, batch_end, &mut rng);
+
+    SimulatedRun {
+        device_start: boot as Tickstamp,
+        batches,
+        dropped_batches,
+        bounced_ticks,
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct LatencyStats {
+    pub count: usize,
+    pub p50: Duration,
+    pub p95: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl LatencyStats {
+    // nearest-rank percentiles
+    pub fn new(mut samples: Vec<Duration>) -> Option<LatencyStats> {
+        if samples.is_empty() {
+            return None;
+        }
+        samples.sort();
+        let percentile = |p: f64| {
+            let rank = (p * samples.len() as f64).ceil() as usize;
+            samples[rank.max(1) - 1]
+        };
+        Some(LatencyStats {
+            count: samples.len(),
+            p50: percentile(0.5),
+            p95: percentile(0.95),
+            p99: percentile(0.99),
+            max: *samples.last()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clock::ClockSample,
+        run::{RawData, Summary},
+        test_fixtures::athlete,
+    };
+
+    fn raw_data(run: &SimulatedRun) -> RawData {
+        RawData {
+            start_time: "0".to_string(),
+            tickstamps: run.batches.iter().flat_map(|b| b.ticks.clone()).collect(),
+            clock_samples: vec![],
+            discontinuities: vec![],
+        }
+    }
+
+    fn summarize(run: &SimulatedRun) -> Summary<'static> {
+        Summary::new("simulated", &athlete(), raw_data(run)).unwrap()
+    }
+
+    #[test]
+    fn workouts() {
+        let workout = Workout::parse("300@5, 30@0,60@9.5").unwrap();
+        assert_eq!(
+            workout.0[2],
+            Segment {
+                duration: 60,
+                speed: 9.5
+            }
+        );
+        assert_eq!(workout.duration(), 390);
+        assert!(Workout::parse("300").is_none());
+        assert!(Workout::parse("300@-1").is_none());
+        assert_eq!(Workout::preset("intervals").unwrap().duration(), 1560);
+        assert!(Workout::preset("marathon").is_none());
+    }
+
+    #[test]
+    fn simulated_run_matches_workout() {
+        let workout = Workout::preset("intervals").unwrap();
+        let options = SimulationOptions {
+            bounce: 0.05,
+            ..Default::default()
+        };
+        let run = simulate(&workout, &options);
+        assert!(run.bounced_ticks > 0);
+        assert_eq!(run.dropped_batches, 0);
+        assert!(run.batches.windows(2).all(|b| b[0].send_at < b[1].send_at));
+
+        // bounces are debounced away, so the distance is the workout's
+        let summary = summarize(&run);
+        assert!((summary.total_distance - workout.distance()).abs() < 0.01 * workout.distance());
+        assert!((summary.total_time_secs as i64 - workout.duration() as i64).abs() <= 2);
+        assert_eq!(
+            simulate(&workout, &options).batches,
+            run.batches,
+            "the same seed gives the same run"
+        );
+    }
+
+    #[test]
+    fn simulated_drift_and_drops() {
+        let workout = Workout::preset("steady").unwrap();
+        let options = SimulationOptions {
+            drift_ppm: 1000.,
+            jitter: 0.,
+            bounce: 0.,
+            ..Default::default()
+        };
+        let run = simulate(&workout, &options);
+        let first = run.batches[0].ticks[0];
+        let last = *run.batches.last().unwrap().ticks.last().unwrap();
+        // 1800 seconds of running on a clock that is 0.1% fast
+        assert!(((last - first) as f64 - 1_801_800.).abs() < 200.);
+
+        let dropping = simulate(
+            &workout,
+            &SimulationOptions {
+                drop_batch: 0.5,
+                ..options
+            },
+        );
+        assert!(dropping.dropped_batches > 0);
+        assert_eq!(
+            dropping.batches.len() + dropping.dropped_batches,
+            run.batches.len()
+        );
+    }
+
+    #[test]
+    fn simulated_clock_sync() {
+        let workout = Workout::preset("steady").unwrap();
+        let options = SimulationOptions {
+            drift_ppm: 80.,
+            ..Default::default()
+        };
+        let run = simulate(&workout, &options);
+        // the server notes each post up to 50 ms late
+        let start = 1656202584971;
+        let handshake = ClockSample {
+            device_time: run.device_start,
+            wall_time: start + 20,
+        };
+        let clock_samples = std::iter::once(handshake)
+            .chain(run.batches.iter().enumerate().map(|(i, b)| ClockSample {
+                device_time: b.device_time,
+                wall_time: start + b.send_at + (i as u64 * 7) % 50,
+            }))
+            .collect();
+        let mut raw_data = raw_data(&run);
+        raw_data.start_time = start.to_string();
+        raw_data.clock_samples = clock_samples;
+        let summary = Summary::new("simulated", &athlete(), raw_data).unwrap();
+        let clock_sync = summary.clock_sync.unwrap();
+        assert!((clock_sync.drift_ppm - 80.).abs() < 2., "{:?}", clock_sync);
+        assert!(summary.wall_time(0).abs_diff(start) <= 2);
+        let end = summary.interval_data.last().unwrap();
+        assert!(
+            end.wall_time
+                .abs_diff(start + end.time as u64 * 1_000_000 / 1_000_080)
+                <= 2
+        );
+    }
+
+    #[test]
+    fn latency_percentiles() {
+        let samples = (1..=100).map(Duration::from_millis).collect();
+        let stats = LatencyStats::new(samples).unwrap();
+        assert_eq!(stats.count, 100);
+        assert_eq!(stats.p50, Duration::from_millis(50));
+        assert_eq!(stats.p95, Duration::from_millis(95));
+        assert_eq!(stats.p99, Duration::from_millis(99));
+        assert_eq!(stats.max, Duration::from_millis(100));
+        assert_eq!(LatencyStats::new(vec![]), None);
+    }
+}
@@ -0,0 +1,54 @@
+// athletes and summaries shared by the unit tests
+use std::collections::HashMap;
+
+use crate::{
+    athlete::{default_record_distances, Athlete},
+    run::{DistanceRecordSet, IntervalDatum, LargestRect, Summary},
+};
+
+pub const START_TIME: u64 = 1656202584971; // epoch millis
+
+pub fn athlete() -> Athlete {
+    Athlete {
+        id: "athlete".to_string(),
+        name: "Test".to_string(),
+        weight: 192.,
+        units: Default::default(),
+        record_distances: default_record_distances(),
+    }
+}
+
+// seconds at a steady speed in mph from START_TIME, one datum a second
+pub fn steady_summary(seconds: u32, speed: f32) -> Summary<'static> {
+    let calories = speed / 50.; // a second's worth
+    let interval_data = (1..=seconds)
+        .map(|t| IntervalDatum {
+            time: t * 1000,
+            speed,
+            raw_speed: speed,
+            calories,
+            distance: t as f32 * speed / 3600.,
+            wall_time: START_TIME + t as u64 * 1000,
+        })
+        .collect();
+    Summary {
+        start_time: START_TIME.to_string(),
+        distance_records: DistanceRecordSet(HashMap::new()),
+        total_time_secs: seconds,
+        clock_sync: None,
+        discontinuities: vec![],
+        largest_rect: LargestRect {
+            start_time: 0,
+            end_time: seconds * 1000,
+            height: speed,
+            area: seconds as f32 * speed,
+        },
+        id: "run",
+        athlete_id: "athlete".to_string(),
+        total_calories: seconds as f32 * calories,
+        total_distance: seconds as f32 * speed / 3600.,
+        new_personal_bests: HashMap::new(),
+        debounce: Default::default(),
+        interval_data,
+    }
+}
@@ -0,0 +1,193 @@
+// device millis counters start over when the device reboots, say after a
+// brownout, and wrap after about 49.7 days; ingestion stitches each run's counter
+// readings, ticks and clock samples alike, into one continuous timeline
+use serde::{Deserialize, Serialize};
+
+use crate::run::Tickstamp;
+
+const COUNTER_RANGE: u64 = Tickstamp::MAX as u64 + 1;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub enum DiscontinuityKind {
+    #[serde(rename = "wrap")]
+    Wrap, // the counter overflowed, no time is lost
+    #[serde(rename = "reset")]
+    Reset, // the device restarted, the time it was down is unknown
+}
+
+impl DiscontinuityKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DiscontinuityKind::Wrap => "wrap",
+            DiscontinuityKind::Reset => "reset",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub struct Discontinuity {
+    #[serde(rename = "kind")]
+    pub kind: DiscontinuityKind,
+    // of the first reading after it: on the stitched timeline in raw data and
+    // archives, millis since the first tick in summaries
+    #[serde(rename = "time")]
+    pub time: u64,
+}
+
+// where a run's timeline stands, cached between posts
+#[derive(Serialize, Deserialize, Debug, PartialEq, Default)]
+pub struct Timeline {
+    #[serde(rename = "offset")]
+    offset: u64, // added to readings of the current counter
+    #[serde(rename = "latest")]
+    latest: Option<Tickstamp>, // the highest reading of the current counter
+    #[serde(rename = "discontinuities")]
+    pub discontinuities: Vec<Discontinuity>,
+}
+
+impl Timeline {
+    // readings in the order the device took them; ones up to max_rewind behind
+    // the latest are just late, further back the counter started over
+    pub fn stitch(&mut self, reading: Tickstamp, max_rewind: u32) -> u64 {
+        let latest = match self.latest {
+            Some(latest) => latest,
+            None => {
+                self.latest = Some(reading);
+                return self.offset + reading as u64;
+            }
+        };
+        let behind = latest.wrapping_sub(reading);
+        if reading >= latest || behind <= max_rewind {
+            // a reading from just before a wrap that arrives after it
+            if reading > latest && latest.wrapping_sub(reading) <= max_rewind {
+                if let Some(offset) = self.offset.checked_sub(COUNTER_RANGE) {
+                    return offset + reading as u64;
+                }
+            }
+            self.latest = Some(latest.max(reading));
+            return self.offset + reading as u64;
+        }
+        // a wrap carries on right where the counter left off, so it comes from
+        // near the top of the range to near 0; anything else is a restarted
+        // counter, which has been running for at least reading millis
+        let wrapped = Tickstamp::MAX - latest <= max_rewind && reading <= max_rewind;
+        let kind = match wrapped {
+            true => {
+                self.offset += COUNTER_RANGE;
+                DiscontinuityKind::Wrap
+            }
+            false => {
+                self.offset += latest as u64;
+                DiscontinuityKind::Reset
+            }
+        };
+        self.latest = Some(reading);
+        let time = self.offset + reading as u64;
+        self.discontinuities.push(Discontinuity { kind, time });
+        time
+    }
+
+    // the first reset, which clock syncing can't see past
+    pub fn first_reset(discontinuities: &[Discontinuity]) -> Option<u64> {
+        discontinuities
+            .iter()
+            .find(|d| d.kind == DiscontinuityKind::Reset)
+            .map(|d| d.time)
+    }
+}
+
+// stitched times are kept as they are unless the run went past the counter's
+// range, as across a wrap, in which case they are moved to start at 0
+pub fn base(first: u64, last: u64) -> u64 {
+    match last > Tickstamp::MAX as u64 {
+        true => first,
+        false => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAX_REWIND: u32 = 60_000;
+
+    fn stitch(readings: &[Tickstamp]) -> (Vec<u64>, Timeline) {
+        let mut timeline = Timeline::default();
+        let times = readings
+            .iter()
+            .map(|r| timeline.stitch(*r, MAX_REWIND))
+            .collect();
+        (times, timeline)
+    }
+
+    #[test]
+    fn stitch_continuous() {
+        let (times, timeline) = stitch(&[5000, 5400, 5300, 5900]);
+        assert_eq!(times, vec![5000, 5400, 5300, 5900]);
+        assert_eq!(timeline.discontinuities, vec![]);
+    }
+
+    #[test]
+    fn stitch_wrap() {
+        let max = Tickstamp::MAX;
+        let (times, timeline) = stitch(&[max - 700, max - 200, 300, max - 100, 800]);
+        let max = max as u64;
+        assert_eq!(
+            times,
+            vec![max - 700, max - 200, max + 301, max - 100, max + 801]
+        );
+        assert_eq!(
+            timeline.discontinuities,
+            vec![Discontinuity {
+                kind: DiscontinuityKind::Wrap,
+                time: max + 301,
+            }]
+        );
+        assert_eq!(base(times[0], times[4]), max - 700);
+        assert_eq!(base(5000, 5900), 0);
+    }
+
+    #[test]
+    fn stitch_resets() {
+        // two brownouts, ten minutes in and two minutes later
+        let (times, timeline) = stitch(&[4_000_000, 4_600_000, 3000, 120_000, 2500, 2900]);
+        assert_eq!(
+            times,
+            vec![4_000_000, 4_600_000, 4_603_000, 4_720_000, 4_722_500, 4_722_900]
+        );
+        assert_eq!(
+            timeline.discontinuities,
+            vec![
+                Discontinuity {
+                    kind: DiscontinuityKind::Reset,
+                    time: 4_603_000,
+                },
+                Discontinuity {
+                    kind: DiscontinuityKind::Reset,
+                    time: 4_722_500,
+                },
+            ]
+        );
+        assert_eq!(
+            Timeline::first_reset(&timeline.discontinuities),
+            Some(4_603_000)
+        );
+    }
+
+    #[test]
+    fn stitch_reset_after_long_uptime() {
+        // a reboot more than 2^31 millis, some 25 days, into the counter
+        let (times, timeline) = stitch(&[3_000_000_000, 3_000_400_000, 3000, 3400]);
+        assert_eq!(
+            times,
+            vec![3_000_000_000, 3_000_400_000, 3_000_403_000, 3_000_403_400]
+        );
+        assert_eq!(
+            timeline.discontinuities,
+            vec![Discontinuity {
+                kind: DiscontinuityKind::Reset,
+                time: 3_000_403_000,
+            }]
+        );
+    }
+}